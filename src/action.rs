@@ -1,39 +1,152 @@
-use crate::entity::Entity;
+use crate::entity::EntityRef;
 use crate::world::{Direction, World};
 
+#[derive(Copy, Clone, Debug)]
 pub enum Action {
     Wait,
     Move(Direction),
     Turn(Direction),
+    /// Still carrying out an earlier action, so no action was determined.
+    Busy,
 }
 
+#[derive(Copy, Clone, Debug)]
 pub enum Outcome {
     Blocked,
     Wait,
     Move(Direction),
     Turn(Direction),
+    Busy,
+}
+
+/// Packs a variant number and an optional direction into a byte, for compact storage.
+fn to_code(variant: u8, direction: Option<&Direction>) -> u8 {
+    (variant << 4) | direction.map_or(0, |direction| direction.index() as u8)
+}
+
+fn direction_of_code(code: u8) -> Option<Direction> {
+    Direction::from_index((code & 0x0F) as usize)
+}
+
+impl Outcome {
+    pub fn to_code(&self) -> u8 {
+        match self {
+            Outcome::Blocked => to_code(0, None),
+            Outcome::Wait => to_code(1, None),
+            Outcome::Move(direction) => to_code(2, Some(direction)),
+            Outcome::Turn(direction) => to_code(3, Some(direction)),
+            Outcome::Busy => to_code(4, None),
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Outcome> {
+        match code >> 4 {
+            0 => Some(Outcome::Blocked),
+            1 => Some(Outcome::Wait),
+            2 => direction_of_code(code).map(Outcome::Move),
+            3 => direction_of_code(code).map(Outcome::Turn),
+            4 => Some(Outcome::Busy),
+            _ => None,
+        }
+    }
 }
 
 impl Action {
+    pub fn to_code(&self) -> u8 {
+        match self {
+            Action::Wait => to_code(0, None),
+            Action::Move(direction) => to_code(1, Some(direction)),
+            Action::Turn(direction) => to_code(2, Some(direction)),
+            Action::Busy => to_code(3, None),
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<Action> {
+        match code >> 4 {
+            0 => Some(Action::Wait),
+            1 => direction_of_code(code).map(Action::Move),
+            2 => direction_of_code(code).map(Action::Turn),
+            3 => Some(Action::Busy),
+            _ => None,
+        }
+    }
+
     pub fn conflicting_directions(&self) -> Option<Vec<Direction>> {
         match self {
             Action::Wait => None,
             Action::Move(direction) => Some(vec![*direction]),
             Action::Turn(_) => None,
+            Action::Busy => None,
         }
     }
 
-    pub fn resolve(&self, entity: &Entity, world: &World) -> Outcome {
+    pub fn resolve(&self, entity: &EntityRef, world: &World) -> Outcome {
         match self {
             Action::Wait => Outcome::Wait,
             Action::Move(direction) => {
-                let target_location = world.add(&entity.location, direction);
-                match world.get_entity(&target_location) {
+                let Some(target_location) = world.target(&entity.location(), direction) else {
+                    return Outcome::Blocked;
+                };
+                match world.get_entity_id(&target_location) {
                     Some(_) => Outcome::Blocked,
                     None => Outcome::Move(*direction),
                 }
             }
             Action::Turn(facing) => Outcome::Turn(*facing),
+            Action::Busy => Outcome::Busy,
         }
     }
 }
+
+/// How many steps each kind of outcome takes an entity, where a step is as many ticks as the entity's interval.
+/// The outcome happens at once, and the entity is busy for the rest of the time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ActionDurations {
+    pub wait: u8,
+    pub moving: u8,
+    pub turn: u8,
+    /// Giving up on a blocked move.
+    pub blocked: u8,
+}
+
+impl ActionDurations {
+    pub const NAMES: [&'static str; 4] = ["wait", "move", "turn", "blocked"];
+
+    /// The steps of the outcome, which are always at least one.
+    pub fn steps(&self, outcome: &Outcome) -> u8 {
+        let steps = match outcome {
+            Outcome::Wait => self.wait,
+            Outcome::Move(_) => self.moving,
+            Outcome::Turn(_) => self.turn,
+            Outcome::Blocked => self.blocked,
+            Outcome::Busy => 1,
+        };
+        steps.max(1)
+    }
+
+    /// The duration with the given name in NAMES, for setting from scenarios.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut u8> {
+        match name {
+            "wait" => Some(&mut self.wait),
+            "move" => Some(&mut self.moving),
+            "turn" => Some(&mut self.turn),
+            "blocked" => Some(&mut self.blocked),
+            _ => None,
+        }
+    }
+
+    pub fn to_array(&self) -> [u8; 4] {
+        [self.wait, self.moving, self.turn, self.blocked]
+    }
+
+    pub fn from_array([wait, moving, turn, blocked]: [u8; 4]) -> ActionDurations {
+        ActionDurations { wait, moving, turn, blocked }
+    }
+}
+
+impl Default for ActionDurations {
+    /// Every outcome takes one step.
+    fn default() -> ActionDurations {
+        ActionDurations { wait: 1, moving: 1, turn: 1, blocked: 1 }
+    }
+}
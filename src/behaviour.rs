@@ -0,0 +1,49 @@
+use crate::action::{Action, Outcome};
+use crate::entity::EntityRef;
+use crate::rng_buffer;
+use crate::world::World;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Behaviour {
+    /// Mostly moves forward, occasionally turning or waiting at random.
+    Wanderer,
+    /// Always moves forward, turning to a random direction only after being blocked.
+    Cruiser,
+    /// Mostly waits, occasionally moving or turning.
+    Idler,
+}
+
+impl Behaviour {
+    pub const ALL: [Behaviour; 3] = [Behaviour::Wanderer, Behaviour::Cruiser, Behaviour::Idler];
+
+    pub fn index(&self) -> usize {
+        Self::ALL.iter().position(|behaviour| behaviour == self).unwrap()
+    }
+
+    pub fn from_index(index: usize) -> Option<Behaviour> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn random() -> Behaviour {
+        Self::ALL[(rng_buffer::next() * Self::ALL.len() as f64) as usize % Self::ALL.len()]
+    }
+
+    pub fn determine_action(&self, entity: &EntityRef, world: &World) -> Action {
+        match self {
+            Behaviour::Wanderer => match rng_buffer::next() {
+                roll if roll < 0.05 => Action::Turn(world.topology().random_direction()),
+                roll if roll < 0.95 => Action::Move(entity.facing()),
+                _ => Action::Wait,
+            },
+            Behaviour::Cruiser => match entity.last_outcome() {
+                Outcome::Blocked => Action::Turn(world.topology().random_direction()),
+                _ => Action::Move(entity.facing()),
+            },
+            Behaviour::Idler => match rng_buffer::next() {
+                roll if roll < 0.05 => Action::Turn(world.topology().random_direction()),
+                roll if roll < 0.30 => Action::Move(entity.facing()),
+                _ => Action::Wait,
+            },
+        }
+    }
+}
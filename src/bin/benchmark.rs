@@ -0,0 +1,266 @@
+//! Runs the simulation headless over every combination of entity count, world size, thread count and entity layout,
+//! and writes the tick rate and per-phase timings of each run as CSV and JSON.
+//!
+//! Example:
+//!     cargo run --release --bin benchmark -- --entities 10000,50000 --sizes 900x450,1800x900 --threads 1,4 --layouts aos,soa --ticks 500
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant};
+use graphics_test::{rng_buffer, test_world, world_processor};
+use graphics_test::entity_storage::EntityLayout;
+use graphics_test::execution::Execution;
+use graphics_test::profiler::Phase;
+use graphics_test::world::World;
+
+/// Phases that run when stepping headless.
+const PHASES: [Phase; 8] = [
+    Phase::Step,
+    Phase::CleanUp,
+    Phase::GetLocations,
+    Phase::DetermineActions,
+    Phase::ResolveConflicts,
+    Phase::DetermineOutcomes,
+    Phase::RecordHeatmap,
+    Phase::ApplyOutcomes,
+];
+
+const USAGE: &str = "\
+Usage: benchmark [options]
+
+Options:
+    --entities <n,...>        entity counts to run (default 50000)
+    --sizes <WxH,...>         world sizes to run (default 1800x900)
+    --threads <n,...>         thread pool sizes to run (default: number of CPUs)
+    --sequential              run on one thread without a pool, instead of sweeping thread counts
+    --layouts <aos|soa,...>   entity layouts to run (default aos)
+    --ticks <n>               timed ticks per run (default 1000)
+    --warmup <n>              untimed ticks before each run (default 50)
+    --seed <n>                RNG seed for every run (default 0)
+    --csv <path>              where to write CSV results (default bench_results.csv)
+    --json <path>             where to write JSON results (default bench_results.json)
+    --help                    show this message";
+
+struct Config {
+    entity_counts: Vec<u32>,
+    sizes: Vec<(usize, usize)>,
+    thread_counts: Vec<usize>,
+    sequential: bool,
+    layouts: Vec<EntityLayout>,
+    ticks: u64,
+    warmup: u64,
+    seed: u64,
+    csv_path: String,
+    json_path: String,
+}
+
+struct RunResult {
+    entities: u32,
+    width: usize,
+    height: usize,
+    threads: usize,
+    sequential: bool,
+    layout: EntityLayout,
+    ticks: u64,
+    seed: u64,
+    elapsed: Duration,
+    /// Bytes of scratch storage the processor held while stepping.
+    scratch_bytes: usize,
+    /// Mean and 95th percentile duration of each of PHASES.
+    phases: Vec<(Duration, Duration)>,
+}
+
+impl RunResult {
+    fn ticks_per_second(&self) -> f64 {
+        self.ticks as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+fn main() {
+    let config = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut results = Vec::new();
+    for &(width, height) in &config.sizes {
+        for &entities in &config.entity_counts {
+            for &threads in &config.thread_counts {
+                for &layout in &config.layouts {
+                    let result = run(&config, width, height, entities, threads, layout);
+                    println!(
+                        "{}x{}, {} entities, {} threads, {}: {:.1} ticks/s (step mean {:.3} ms, scratch {:.1} MB)",
+                        width, height, entities, threads, layout.name(), result.ticks_per_second(),
+                        millis(result.phases[0].0), megabytes(result.scratch_bytes),
+                    );
+                    results.push(result);
+                }
+            }
+        }
+    }
+
+    write_csv(&config.csv_path, &results).expect("should be able to write CSV results");
+    write_json(&config.json_path, &results).expect("should be able to write JSON results");
+    println!("wrote results to {} and {}", config.csv_path, config.json_path);
+}
+
+fn run(config: &Config, width: usize, height: usize, entities: u32, threads: usize, layout: EntityLayout) -> RunResult {
+    let execution = if config.sequential { Execution::Sequential } else { Execution::Parallel(threads) };
+    world_processor::set_execution(execution).expect("should be able to build thread pool");
+
+    rng_buffer::init_with_seed(config.seed);
+    let mut world = World::new(width, height);
+    test_world::load(&mut world, entities.min(u32::try_from(width * height).unwrap_or(u32::MAX)));
+    world.set_entity_layout(layout);
+    world_processor::take_world();
+    world_processor::init(world).unwrap();
+    world_processor::set_drawing_enabled(false);
+    // The heatmap is only there to be drawn, so it shouldn't count towards the step.
+    world_processor::set_heatmap_enabled(false);
+
+    for _ in 0..config.warmup {
+        world_processor::step(false);
+    }
+    world_processor::set_profile_window(config.ticks as usize);
+
+    let start = Instant::now();
+    for _ in 0..config.ticks {
+        world_processor::step(false);
+    }
+    let elapsed = start.elapsed();
+
+    RunResult {
+        entities,
+        width,
+        height,
+        threads,
+        sequential: config.sequential,
+        layout,
+        ticks: config.ticks,
+        seed: config.seed,
+        elapsed,
+        scratch_bytes: world_processor::scratch_bytes(),
+        phases: PHASES.iter()
+            .map(|phase| world_processor::phase_stats(*phase))
+            .map(|stats| (stats.mean, stats.p95))
+            .collect(),
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Config, String> {
+    let mut config = Config {
+        entity_counts: vec![50_000],
+        sizes: vec![(1800, 900)],
+        thread_counts: vec![std::thread::available_parallelism().map_or(1, |n| n.get())],
+        sequential: false,
+        layouts: vec![EntityLayout::Aos],
+        ticks: 1000,
+        warmup: 50,
+        seed: 0,
+        csv_path: String::from("bench_results.csv"),
+        json_path: String::from("bench_results.json"),
+    };
+
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        if flag == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        if flag == "--sequential" {
+            config.sequential = true;
+            continue;
+        }
+        let value = args.next().ok_or(format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--entities" => config.entity_counts = parse_list(&value, parse_number)?,
+            "--sizes" => config.sizes = parse_list(&value, parse_size)?,
+            "--threads" => config.thread_counts = parse_list(&value, parse_number)?,
+            "--layouts" => config.layouts = parse_list(&value, parse_layout)?,
+            "--ticks" => config.ticks = parse_number(&value)?,
+            "--warmup" => config.warmup = parse_number(&value)?,
+            "--seed" => config.seed = parse_number(&value)?,
+            "--csv" => config.csv_path = value,
+            "--json" => config.json_path = value,
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+    if config.sequential {
+        config.thread_counts = vec![1];
+    }
+    Ok(config)
+}
+
+fn parse_list<T>(value: &str, parse: fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    value.split(',').map(|item| parse(item.trim())).collect()
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("expected a number but found '{}'", value))
+}
+
+fn parse_layout(value: &str) -> Result<EntityLayout, String> {
+    EntityLayout::from_name(value).ok_or(format!("expected aos or soa but found '{}'", value))
+}
+
+fn parse_size(value: &str) -> Result<(usize, usize), String> {
+    let (width, height) = value.split_once('x').ok_or(format!("expected WxH but found '{}'", value))?;
+    Ok((parse_number(width)?, parse_number(height)?))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+fn megabytes(bytes: usize) -> f64 {
+    bytes as f64 / 1_000_000.0
+}
+
+fn write_csv(path: &str, results: &[RunResult]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "entities,width,height,threads,sequential,layout,ticks,seed,seconds,ticks_per_second,scratch_bytes")?;
+    for phase in PHASES {
+        write!(out, ",{0}_mean_us,{0}_p95_us", phase.name())?;
+    }
+    writeln!(out)?;
+
+    for result in results {
+        write!(
+            out, "{},{},{},{},{},{},{},{},{:.6},{:.3},{}",
+            result.entities, result.width, result.height, result.threads, result.sequential, result.layout.name(),
+            result.ticks, result.seed, result.elapsed.as_secs_f64(), result.ticks_per_second(), result.scratch_bytes,
+        )?;
+        for (mean, p95) in &result.phases {
+            write!(out, ",{:.3},{:.3}", micros(*mean), micros(*p95))?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+fn write_json(path: &str, results: &[RunResult]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "[")?;
+    for (i, result) in results.iter().enumerate() {
+        write!(
+            out, "  {{\"entities\":{},\"width\":{},\"height\":{},\"threads\":{},\"sequential\":{},\"layout\":\"{}\",\"ticks\":{},\"seed\":{},\"seconds\":{:.6},\"ticks_per_second\":{:.3},\"scratch_bytes\":{},\"phases\":{{",
+            result.entities, result.width, result.height, result.threads, result.sequential, result.layout.name(),
+            result.ticks, result.seed, result.elapsed.as_secs_f64(), result.ticks_per_second(), result.scratch_bytes,
+        )?;
+        for (j, (phase, (mean, p95))) in PHASES.iter().zip(&result.phases).enumerate() {
+            let separator = if j == 0 { "" } else { "," };
+            write!(out, "{}\"{}\":{{\"mean_us\":{:.3},\"p95_us\":{:.3}}}", separator, phase.name(), micros(*mean), micros(*p95))?;
+        }
+        let separator = if i + 1 == results.len() { "" } else { "," };
+        writeln!(out, "}}}}{}", separator)?;
+    }
+    writeln!(out, "]")?;
+    out.flush()
+}
@@ -0,0 +1,91 @@
+//! Reconstructs the world at a given tick of a recording made with the event log, and saves it as a snapshot.
+//!
+//! Example:
+//!     cargo run --release --bin replay -- recording --tick 500 --out tick_500.snapshot --entity 42
+
+use graphics_test::{event_log, snapshot};
+
+const USAGE: &str = "\
+Usage: replay <recording dir> [options]
+
+Options:
+    --tick <n>                tick to reconstruct the world at the start of (default: end of recording)
+    --out <path>              where to save the reconstructed world as a snapshot
+    --entity <id>             print the state of the entity with this id
+    --help                    show this message";
+
+struct Config {
+    dir: String,
+    tick: Option<u64>,
+    out: Option<String>,
+    entity: Option<usize>,
+}
+
+fn main() {
+    let config = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let (world, tick) = match event_log::replay(&config.dir, config.tick) {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("failed to replay {}: {}", config.dir, error);
+            std::process::exit(1);
+        }
+    };
+    if config.tick.is_some_and(|requested| requested != tick) {
+        println!("recording ends before tick {}", config.tick.unwrap());
+    }
+    println!("reconstructed tick {}: {}x{} world with {} entities", tick, world.width(), world.height(), world.entity_count());
+
+    if let Some(id) = config.entity {
+        match world.entity(id) {
+            None => println!("there is no entity {}", id),
+            Some(entity) => println!(
+                "entity {}: at ({}, {}) facing {:?}, {:?}, lineage {}, age {}, energy {:.3}, last outcome {:?}",
+                id, entity.location().x(), entity.location().y(), entity.facing(), entity.behaviour(),
+                entity.lineage(), entity.age(), entity.energy(), entity.last_outcome(),
+            ),
+        }
+    }
+
+    if let Some(out) = &config.out {
+        match snapshot::save(out, &world, tick) {
+            Ok(()) => println!("saved snapshot to {}", out),
+            Err(error) => {
+                eprintln!("failed to save snapshot to {}: {}", out, error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Config, String> {
+    let mut dir = None;
+    let mut config = Config { dir: String::new(), tick: None, out: None, entity: None };
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        if !arg.starts_with("--") {
+            dir = Some(arg);
+            continue;
+        }
+        let value = args.next().ok_or(format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--tick" => config.tick = Some(value.parse().map_err(|_| format!("invalid tick '{}'", value))?),
+            "--out" => config.out = Some(value),
+            "--entity" => config.entity = Some(value.parse().map_err(|_| format!("invalid entity id '{}'", value))?),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+    config.dir = dir.ok_or("missing recording directory")?;
+    Ok(config)
+}
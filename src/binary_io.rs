@@ -0,0 +1,58 @@
+//! Little-endian reading and writing of the primitive values used by snapshots and event logs.
+
+use std::io::{Read, Result, Write};
+
+pub fn write_u8(out: &mut impl Write, value: u8) -> Result<()> {
+    out.write_all(&[value])
+}
+
+pub fn write_u32(out: &mut impl Write, value: u32) -> Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64(out: &mut impl Write, value: u64) -> Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn write_f32(out: &mut impl Write, value: f32) -> Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn read_u8(input: &mut impl Read) -> Result<u8> {
+    let mut bytes = [0; 1];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub fn read_u32(input: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(input: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f32(input: &mut impl Read) -> Result<f32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+/// For use with map_err when reading into a Result with a String error.
+pub fn describe(error: std::io::Error) -> String {
+    error.to_string()
+}
+
+/// Reads and checks the magic bytes at the start of a file.
+pub fn expect_magic(input: &mut impl Read, magic: &[u8]) -> std::result::Result<(), String> {
+    let mut bytes = vec![0; magic.len()];
+    input.read_exact(&mut bytes).map_err(describe)?;
+    if bytes != magic {
+        return Err(format!("expected file to start with {:?}", String::from_utf8_lossy(magic)));
+    }
+    Ok(())
+}
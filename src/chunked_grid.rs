@@ -0,0 +1,187 @@
+//! Grids stored as square chunks that are only allocated once something is written to them, and can be freed
+//! again once they aren't needed, so that a very large world only takes memory where it is occupied.
+
+use std::marker::PhantomData;
+
+/// The width and height of a chunk, in cells. A power of two, so that finding a cell's chunk is cheap.
+pub const CHUNK_SIZE: usize = 64;
+const CHUNK_SHIFT: u32 = CHUNK_SIZE.trailing_zeros();
+const CHUNK_CELLS: usize = CHUNK_SIZE * CHUNK_SIZE;
+
+type Chunk<T> = Box<[T; CHUNK_CELLS]>;
+
+#[derive(Clone)]
+pub struct ChunkedGrid<T> {
+    width: usize,
+    height: usize,
+    /// The number of chunks across the grid.
+    columns: usize,
+    chunks: Vec<Option<Chunk<T>>>,
+    allocated_count: usize,
+    /// What every cell of an unallocated chunk holds.
+    empty: T,
+}
+
+impl<T: Default> ChunkedGrid<T> {
+    getter!(width: usize);
+    getter!(height: usize);
+
+    pub fn new(width: usize, height: usize) -> ChunkedGrid<T> {
+        let columns = width.div_ceil(CHUNK_SIZE);
+        ChunkedGrid {
+            width,
+            height,
+            columns,
+            chunks: (0..columns * height.div_ceil(CHUNK_SIZE)).map(|_| None).collect(),
+            allocated_count: 0,
+            empty: T::default(),
+        }
+    }
+
+    /// The index of the chunk holding (x, y).
+    #[inline]
+    pub fn chunk_of(&self, x: usize, y: usize) -> usize {
+        (y >> CHUNK_SHIFT) * self.columns + (x >> CHUNK_SHIFT)
+    }
+
+    /// The index of (x, y) within its chunk.
+    #[inline]
+    fn cell_of(x: usize, y: usize) -> usize {
+        ((y & (CHUNK_SIZE - 1)) << CHUNK_SHIFT) | (x & (CHUNK_SIZE - 1))
+    }
+
+    /// Whether (x, y) is one step from another chunk, including across the edges of the grid.
+    pub fn is_on_chunk_edge(&self, x: usize, y: usize) -> bool {
+        let (cell_x, cell_y) = (x & (CHUNK_SIZE - 1), y & (CHUNK_SIZE - 1));
+        cell_x == 0 || cell_x == CHUNK_SIZE - 1 || x == self.width - 1
+            || cell_y == 0 || cell_y == CHUNK_SIZE - 1 || y == self.height - 1
+    }
+
+    /// The position of a cell of a chunk, which may be outside the grid for chunks on its right and bottom edges.
+    pub fn position(&self, chunk: usize, cell: usize) -> (usize, usize) {
+        (
+            (chunk % self.columns) * CHUNK_SIZE + cell % CHUNK_SIZE,
+            (chunk / self.columns) * CHUNK_SIZE + cell / CHUNK_SIZE,
+        )
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The number of cells in allocated chunks.
+    pub fn allocated_cells(&self) -> usize {
+        self.allocated_count * CHUNK_CELLS
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> &T {
+        self.get_allocated(x, y).unwrap_or(&self.empty)
+    }
+
+    /// The cell at (x, y), or None if its chunk hasn't been allocated.
+    pub fn get_allocated(&self, x: usize, y: usize) -> Option<&T> {
+        self.chunks[self.chunk_of(x, y)].as_ref().map(|chunk| &chunk[Self::cell_of(x, y)])
+    }
+
+    /// The cell at (x, y), allocating its chunk if it hasn't been.
+    pub fn get_mut(&mut self, x: usize, y: usize) -> &mut T {
+        let chunk = self.chunk_of(x, y);
+        &mut self.allocate(chunk)[Self::cell_of(x, y)]
+    }
+
+    /// Allocates the chunk if it hasn't been, returning its cells.
+    pub fn allocate(&mut self, chunk: usize) -> &mut [T; CHUNK_CELLS] {
+        let slot = &mut self.chunks[chunk];
+        if slot.is_none() {
+            self.allocated_count += 1;
+        }
+        slot.get_or_insert_with(|| Box::new(std::array::from_fn(|_| T::default())))
+    }
+
+    /// The allocated chunks and their indices, in index order.
+    pub fn allocated_chunks(&self) -> Vec<(usize, &[T])> {
+        self.chunks.iter().enumerate()
+            .filter_map(|(index, chunk)| chunk.as_ref().map(|chunk| (index, &chunk[..])))
+            .collect()
+    }
+
+    /// Frees the chunk if it is allocated, so that its cells read as empty again.
+    pub fn free(&mut self, chunk: usize) {
+        if self.chunks[chunk].take().is_some() {
+            self.allocated_count -= 1;
+        }
+    }
+
+    /// Frees every chunk not in the given chunks, which must be in index order.
+    pub fn retain_chunks(&mut self, chunks: &[usize]) {
+        for chunk in 0..self.chunks.len() {
+            if self.chunks[chunk].is_some() && chunks.binary_search(&chunk).is_err() {
+                self.free(chunk);
+            }
+        }
+    }
+
+    /// Frees every chunk.
+    pub fn clear(&mut self) {
+        self.chunks.iter_mut().for_each(|chunk| *chunk = None);
+        self.allocated_count = 0;
+    }
+
+    /// A writer for writing to distinct cells of allocated chunks from many threads at once.
+    pub fn disjoint_writer(&mut self) -> DisjointWriter<'_, T> {
+        DisjointWriter {
+            chunks: self.chunks.iter_mut()
+                .map(|chunk| chunk.as_mut().map_or(std::ptr::null_mut(), |chunk| chunk.as_mut_ptr()))
+                .collect(),
+            columns: self.columns,
+            _grid: PhantomData,
+        }
+    }
+}
+
+/// Lets many threads write to distinct cells of a grid at once.
+pub struct DisjointWriter<'a, T> {
+    /// The cells of each chunk, or null for chunks that aren't allocated.
+    chunks: Vec<*mut T>,
+    columns: usize,
+    _grid: PhantomData<&'a mut ChunkedGrid<T>>,
+}
+
+// Safety: Writes from different threads are to different cells, as required by write.
+unsafe impl<T: Send> Sync for DisjointWriter<'_, T> {}
+
+impl<T: Default> DisjointWriter<'_, T> {
+    /// # Safety
+    /// No other thread may read or write the cell at (x, y) while this writer is in use.
+    pub unsafe fn write(&self, x: usize, y: usize, value: T) {
+        let chunk = self.chunks[(y >> CHUNK_SHIFT) * self.columns + (x >> CHUNK_SHIFT)];
+        assert!(!chunk.is_null(), "chunk should be allocated");
+        *chunk.add(ChunkedGrid::<T>::cell_of(x, y)) = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_chunks_read_as_empty() {
+        let mut grid: ChunkedGrid<u32> = ChunkedGrid::new(3 * CHUNK_SIZE, 2 * CHUNK_SIZE);
+        *grid.get_mut(0, 0) = 1;
+        *grid.get_mut(CHUNK_SIZE, 0) = 2;
+        *grid.get_mut(2 * CHUNK_SIZE + 5, CHUNK_SIZE + 7) = 3;
+        assert_eq!(grid.allocated_cells(), 3 * CHUNK_CELLS);
+
+        grid.retain_chunks(&[1, 5]);
+        assert_eq!(grid.allocated_cells(), 2 * CHUNK_CELLS);
+        assert_eq!(grid.get_allocated(0, 0), None);
+        assert_eq!(*grid.get(0, 0), 0);
+        assert_eq!(*grid.get(CHUNK_SIZE, 0), 2);
+
+        grid.free(5);
+        grid.free(5);
+        assert_eq!(grid.allocated_cells(), CHUNK_CELLS);
+        assert_eq!(*grid.get(2 * CHUNK_SIZE + 5, CHUNK_SIZE + 7), 0);
+        assert_eq!(grid.allocated_chunks().iter().map(|(chunk, _)| *chunk).collect::<Vec<_>>(), vec![1]);
+    }
+}
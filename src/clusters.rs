@@ -0,0 +1,169 @@
+//! Groups of entities in touching cells, for measuring how entities jam together.
+//! Cells are connected to all their neighbours in the world's topology, across the edges if the world wraps.
+
+use crate::execution;
+use crate::world::{EntityId, World};
+
+/// The number of buckets in a size histogram. Bucket i counts clusters of 2^i to 2^(i+1) - 1 entities,
+/// apart from the last, which counts every cluster too big for the others.
+pub const SIZE_BUCKETS: usize = 8;
+
+/// Which cluster each entity is in. Clusters are numbered in order of the lowest entity id in each.
+pub struct Clusters {
+    labels: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl Clusters {
+    pub fn find(world: &World) -> Clusters {
+        let locations = world.entities().locations();
+        // Half of the directions, so that each pair of neighbours is only joined once.
+        let forward: Vec<_> = world.directions().iter()
+            .filter(|direction| direction.index() < direction.opposite().index())
+            .collect();
+        let neighbours = execution::map(&locations, |location| {
+            forward.iter()
+                .filter_map(|direction| world.neighbour(location, direction).and_then(|neighbour| world.get_entity_id(&neighbour)))
+                .collect::<Vec<_>>()
+        });
+
+        let mut sets = DisjointSets::new(locations.len());
+        for (id, neighbours) in neighbours.iter().enumerate() {
+            for neighbour in neighbours {
+                sets.union(id, *neighbour);
+            }
+        }
+
+        let mut label_of_root = vec![usize::MAX; locations.len()];
+        let mut sizes = Vec::new();
+        let labels = (0..locations.len())
+            .map(|id| {
+                let root = sets.find(id);
+                if label_of_root[root] == usize::MAX {
+                    label_of_root[root] = sizes.len();
+                    sizes.push(0);
+                }
+                sizes[label_of_root[root]] += 1;
+                label_of_root[root]
+            })
+            .collect();
+        Clusters { labels, sizes }
+    }
+
+    pub fn count(&self) -> usize {
+        self.sizes.len()
+    }
+
+    pub fn cluster_of(&self, id: EntityId) -> usize {
+        self.labels[id]
+    }
+
+    /// The number of entities in each cluster, by cluster.
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
+    }
+
+    pub fn size_of(&self, cluster: usize) -> usize {
+        self.sizes[cluster]
+    }
+
+    pub fn largest(&self) -> usize {
+        self.sizes.iter().copied().max().unwrap_or(0)
+    }
+
+    /// How many clusters there are of each range of sizes, as described for SIZE_BUCKETS.
+    pub fn size_histogram(&self) -> [usize; SIZE_BUCKETS] {
+        let mut histogram = [0; SIZE_BUCKETS];
+        for size in &self.sizes {
+            histogram[(size.ilog2() as usize).min(SIZE_BUCKETS - 1)] += 1;
+        }
+        histogram
+    }
+}
+
+/// The name of a size histogram bucket, e.g. 4_7, or 128_up for the last.
+pub fn bucket_name(bucket: usize) -> String {
+    let smallest = 1 << bucket;
+    if bucket == 0 {
+        String::from("1")
+    } else if bucket == SIZE_BUCKETS - 1 {
+        format!("{}_up", smallest)
+    } else {
+        format!("{}_{}", smallest, 2 * smallest - 1)
+    }
+}
+
+/// Union-find over entity ids.
+struct DisjointSets {
+    parents: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(count: usize) -> DisjointSets {
+        DisjointSets { parents: (0..count).collect(), sizes: vec![1; count] }
+    }
+
+    fn find(&mut self, mut id: usize) -> usize {
+        while self.parents[id] != id {
+            self.parents[id] = self.parents[self.parents[id]];
+            id = self.parents[id];
+        }
+        id
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b { return }
+        let (larger, smaller) = if self.sizes[a] >= self.sizes[b] { (a, b) } else { (b, a) };
+        self.parents[smaller] = larger;
+        self.sizes[larger] += self.sizes[smaller];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::Entity;
+    use crate::rng_buffer;
+    use crate::topology::Topology;
+    use crate::world::Boundary;
+
+    /// Entities in a 6x6 world: ids 0 and 1 in opposite corners, 2 and 3 side by side, and 4 on its own.
+    fn world_with(boundary: Boundary, topology: Topology) -> World {
+        let _lock = rng_buffer::lock_for_test();
+        let mut world = World::new(6, 6);
+        world.set_boundary(boundary);
+        world.set_topology(topology).unwrap();
+        for (x, y) in [(0, 0), (5, 5), (2, 2), (3, 2), (2, 4)] {
+            world.place_entity(Entity::new(x, y, &world)).unwrap();
+        }
+        world
+    }
+
+    #[test]
+    fn clusters_join_across_wrapped_corners() {
+        let clusters = Clusters::find(&world_with(Boundary::Wrap, Topology::Moore));
+        assert_eq!(clusters.count(), 3);
+        assert_eq!(clusters.sizes(), [2, 2, 1]);
+        assert_eq!(clusters.cluster_of(1), 0);
+        assert_eq!(clusters.cluster_of(4), 2);
+        assert_eq!(clusters.largest(), 2);
+        assert_eq!(clusters.size_histogram()[..2], [1, 2]);
+    }
+
+    #[test]
+    fn clusters_follow_the_topology_and_boundary() {
+        let clusters = Clusters::find(&world_with(Boundary::Wrap, Topology::VonNeumann));
+        assert_eq!(clusters.sizes(), [1, 1, 2, 1]);
+        let clusters = Clusters::find(&world_with(Boundary::Solid, Topology::Moore));
+        assert_eq!(clusters.sizes(), [1, 1, 2, 1]);
+    }
+
+    #[test]
+    fn bucket_names() {
+        assert_eq!(bucket_name(0), "1");
+        assert_eq!(bucket_name(2), "4_7");
+        assert_eq!(bucket_name(SIZE_BUCKETS - 1), "128_up");
+    }
+}
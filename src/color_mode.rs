@@ -0,0 +1,100 @@
+use crate::action::Outcome;
+use crate::behaviour::Behaviour;
+use crate::clusters::Clusters;
+use crate::entity::{EntityRef, MAX_ENERGY};
+use crate::graphics_window::Color;
+use crate::world::EntityId;
+
+/// Age (in ticks) at which an entity is drawn with the oldest colour.
+const AGE_COLOR_SCALE: f32 = 2000.0;
+
+const YOUNG_COLOR: Color = Color::new(32, 64, 255);
+const OLD_COLOR: Color = Color::new(255, 255, 255);
+const NO_ENERGY_COLOR: Color = Color::new(255, 0, 0);
+const FULL_ENERGY_COLOR: Color = Color::new(0, 255, 0);
+const LONE_COLOR: Color = Color::new(96, 96, 96);
+
+const WHEEL: [Color; 8] = [
+    Color::new(255, 0, 0),
+    Color::new(255, 128, 0),
+    Color::new(255, 255, 0),
+    Color::new(0, 255, 0),
+    Color::new(0, 255, 255),
+    Color::new(0, 64, 255),
+    Color::new(128, 0, 255),
+    Color::new(255, 0, 255),
+];
+
+/// How entities are coloured when drawn.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    Plain,
+    Facing,
+    LastOutcome,
+    Age,
+    Energy,
+    Lineage,
+    Behaviour,
+    /// By the cluster of touching entities each is in, with lone entities in grey.
+    /// This needs every entity's cluster, so is drawn with cluster_color rather than color_of.
+    Cluster,
+}
+
+impl ColorMode {
+    pub const ALL: [ColorMode; 8] = [
+        ColorMode::Plain,
+        ColorMode::Facing,
+        ColorMode::LastOutcome,
+        ColorMode::Age,
+        ColorMode::Energy,
+        ColorMode::Lineage,
+        ColorMode::Behaviour,
+        ColorMode::Cluster,
+    ];
+
+    /// The mode after this one, wrapping back to the first.
+    pub fn next(&self) -> ColorMode {
+        let index = Self::ALL.iter().position(|mode| mode == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMode::Plain => "plain",
+            ColorMode::Facing => "facing",
+            ColorMode::LastOutcome => "last outcome",
+            ColorMode::Age => "age",
+            ColorMode::Energy => "energy",
+            ColorMode::Lineage => "lineage",
+            ColorMode::Behaviour => "behaviour",
+            ColorMode::Cluster => "cluster",
+        }
+    }
+
+    pub fn color_of(&self, entity: &EntityRef) -> Color {
+        match self {
+            ColorMode::Plain => Color::WHITE,
+            ColorMode::Facing => WHEEL[entity.facing().index()],
+            ColorMode::LastOutcome => match entity.last_outcome() {
+                Outcome::Blocked => Color::RED,
+                Outcome::Wait | Outcome::Busy => Color::new(96, 96, 96),
+                Outcome::Move(_) => Color::GREEN,
+                Outcome::Turn(_) => Color::new(255, 255, 0),
+            },
+            ColorMode::Age => YOUNG_COLOR.lerp(OLD_COLOR, entity.age() as f32 / AGE_COLOR_SCALE),
+            ColorMode::Energy => NO_ENERGY_COLOR.lerp(FULL_ENERGY_COLOR, entity.energy() / MAX_ENERGY),
+            ColorMode::Lineage => WHEEL[entity.lineage() as usize % WHEEL.len()],
+            ColorMode::Behaviour => match entity.behaviour() {
+                Behaviour::Wanderer => Color::WHITE,
+                Behaviour::Cruiser => Color::new(255, 128, 0),
+                Behaviour::Idler => Color::new(0, 160, 255),
+            },
+            ColorMode::Cluster => Color::WHITE,
+        }
+    }
+
+    pub fn cluster_color(clusters: &Clusters, id: EntityId) -> Color {
+        let cluster = clusters.cluster_of(id);
+        if clusters.size_of(cluster) == 1 { LONE_COLOR } else { WHEEL[cluster % WHEEL.len()] }
+    }
+}
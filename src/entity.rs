@@ -1,30 +1,80 @@
 use crate::graphics_window::Color;
-use crate::rng_buffer;
-use crate::action::Action;
-use crate::world::{Direction, Location, World};
+use crate::action::{Action, ActionDurations, Outcome};
+use crate::behaviour::Behaviour;
+use crate::color_mode::ColorMode;
+use crate::entity_storage::EntityColumns;
+use crate::world::{Direction, EntityId, Location, World};
 
+pub const MAX_ENERGY: f32 = 1.0;
+const ENERGY_RECOVERY: f32 = 0.01;
+const MOVE_ENERGY_COST: f32 = 0.01;
+const TURN_ENERGY_COST: f32 = 0.005;
+const BLOCKED_ENERGY_COST: f32 = 0.02;
+
+#[derive(Clone)]
 pub struct Entity {
     pub location: Location,
     pub facing: Direction,
+    pub behaviour: Behaviour,
+    pub lineage: u32,
+    pub age: u32,
+    pub energy: f32,
+    pub last_outcome: Outcome,
+    /// How many ticks each step of an action takes: 1 to act every tick, 2 every other tick, and so on.
+    /// An interval of 0 acts like 1.
+    pub interval: u8,
+    /// How many more ticks the entity is busy with its last action for, during which it doesn't determine actions.
+    pub cooldown: u16,
 }
 
 impl Entity {
-    pub fn pixel_color(&self) -> Color {
-        Color::WHITE
+    pub fn pixel_color(&self, mode: ColorMode) -> Color {
+        self.as_ref().pixel_color(mode)
     }
 
     pub fn new(x: usize, y: usize, world: &World) -> Entity {
         Entity {
-            location: Location::at(x, y, &world),
-            facing: Direction::random(),
+            location: Location::at(x, y, world),
+            facing: world.topology().random_direction(),
+            behaviour: Behaviour::Wanderer,
+            lineage: 0,
+            age: 0,
+            energy: MAX_ENERGY,
+            last_outcome: Outcome::Wait,
+            interval: 1,
+            cooldown: 0,
         }
     }
 
     pub fn determine_action(&self, world: &World) -> Action {
-        match rng_buffer::next() {
-            roll if roll < 0.05 => Action::Turn(Direction::random()),
-            roll if roll < 0.95 => Action::Move(self.facing),
-            _ => Action::Wait,
+        self.as_ref().determine_action(world)
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.cooldown > 0
+    }
+
+    /// Updates the entity's own state for the outcome of this tick.
+    /// Moving the entity is left to the World, as it also affects the entity grid.
+    pub fn apply_outcome(&mut self, outcome: &Outcome, durations: &ActionDurations) {
+        self.as_mut().apply_outcome(outcome, durations);
+    }
+
+    pub fn as_ref(&self) -> EntityRef<'_> {
+        EntityRef::Whole(self)
+    }
+
+    pub fn as_mut(&mut self) -> EntityMut<'_> {
+        EntityMut {
+            location: &mut self.location,
+            facing: &mut self.facing,
+            behaviour: &mut self.behaviour,
+            lineage: &mut self.lineage,
+            age: &mut self.age,
+            energy: &mut self.energy,
+            last_outcome: &mut self.last_outcome,
+            interval: &mut self.interval,
+            cooldown: &mut self.cooldown,
         }
     }
 
@@ -34,3 +84,141 @@ impl Entity {
 
     }
 }
+
+/// An entity, borrowed from wherever its fields are stored. Fields are only read when asked for, so that
+/// reading an entity stored as columns only reads the columns of the fields used.
+#[derive(Copy, Clone)]
+pub enum EntityRef<'a> {
+    Whole(&'a Entity),
+    Columns(&'a EntityColumns, EntityId),
+}
+
+impl EntityRef<'_> {
+    pub fn location(&self) -> Location {
+        match self {
+            EntityRef::Whole(entity) => entity.location,
+            EntityRef::Columns(columns, id) => columns.locations()[*id],
+        }
+    }
+
+    pub fn facing(&self) -> Direction {
+        match self {
+            EntityRef::Whole(entity) => entity.facing,
+            EntityRef::Columns(columns, id) => columns.facings()[*id],
+        }
+    }
+
+    pub fn behaviour(&self) -> Behaviour {
+        match self {
+            EntityRef::Whole(entity) => entity.behaviour,
+            EntityRef::Columns(columns, id) => columns.behaviours()[*id],
+        }
+    }
+
+    pub fn lineage(&self) -> u32 {
+        match self {
+            EntityRef::Whole(entity) => entity.lineage,
+            EntityRef::Columns(columns, id) => columns.lineages()[*id],
+        }
+    }
+
+    pub fn age(&self) -> u32 {
+        match self {
+            EntityRef::Whole(entity) => entity.age,
+            EntityRef::Columns(columns, id) => columns.ages()[*id],
+        }
+    }
+
+    pub fn energy(&self) -> f32 {
+        match self {
+            EntityRef::Whole(entity) => entity.energy,
+            EntityRef::Columns(columns, id) => columns.energies()[*id],
+        }
+    }
+
+    pub fn last_outcome(&self) -> Outcome {
+        match self {
+            EntityRef::Whole(entity) => entity.last_outcome,
+            EntityRef::Columns(columns, id) => columns.last_outcomes()[*id],
+        }
+    }
+
+    pub fn interval(&self) -> u8 {
+        match self {
+            EntityRef::Whole(entity) => entity.interval,
+            EntityRef::Columns(columns, id) => columns.intervals()[*id],
+        }
+    }
+
+    pub fn cooldown(&self) -> u16 {
+        match self {
+            EntityRef::Whole(entity) => entity.cooldown,
+            EntityRef::Columns(columns, id) => columns.cooldowns()[*id],
+        }
+    }
+
+    /// As Entity::pixel_color.
+    pub fn pixel_color(&self, mode: ColorMode) -> Color {
+        mode.color_of(self)
+    }
+
+    /// As Entity::determine_action.
+    pub fn determine_action(&self, world: &World) -> Action {
+        self.behaviour().determine_action(self, world)
+    }
+
+    /// A copy of the entity, gathered from the columns if it is stored as columns.
+    pub fn to_entity(&self) -> Entity {
+        match self {
+            EntityRef::Whole(entity) => (*entity).clone(),
+            EntityRef::Columns(_, _) => Entity {
+                location: self.location(),
+                facing: self.facing(),
+                behaviour: self.behaviour(),
+                lineage: self.lineage(),
+                age: self.age(),
+                energy: self.energy(),
+                last_outcome: self.last_outcome(),
+                interval: self.interval(),
+                cooldown: self.cooldown(),
+            },
+        }
+    }
+}
+
+/// Mutable references to each field of an entity, wherever its fields are stored.
+pub struct EntityMut<'a> {
+    pub location: &'a mut Location,
+    pub facing: &'a mut Direction,
+    pub behaviour: &'a mut Behaviour,
+    pub lineage: &'a mut u32,
+    pub age: &'a mut u32,
+    pub energy: &'a mut f32,
+    pub last_outcome: &'a mut Outcome,
+    pub interval: &'a mut u8,
+    pub cooldown: &'a mut u16,
+}
+
+impl EntityMut<'_> {
+    /// As Entity::apply_outcome.
+    pub fn apply_outcome(&mut self, outcome: &Outcome, durations: &ActionDurations) {
+        let cost = match outcome {
+            Outcome::Blocked => BLOCKED_ENERGY_COST,
+            Outcome::Wait | Outcome::Busy => 0.0,
+            Outcome::Move(_) => MOVE_ENERGY_COST,
+            Outcome::Turn(_) => TURN_ENERGY_COST,
+        };
+        if let Outcome::Turn(facing) = outcome {
+            *self.facing = *facing;
+        }
+        *self.energy = (*self.energy + ENERGY_RECOVERY - cost).clamp(0.0, MAX_ENERGY);
+        *self.age = self.age.saturating_add(1);
+        // The last outcome stays as the action still being carried out.
+        if let Outcome::Busy = outcome {
+            *self.cooldown = self.cooldown.saturating_sub(1);
+        } else {
+            *self.last_outcome = *outcome;
+            *self.cooldown = (*self.interval).max(1) as u16 * durations.steps(outcome) as u16 - 1;
+        }
+    }
+}
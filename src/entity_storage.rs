@@ -0,0 +1,300 @@
+//! How the world lays out its entities in memory: as one struct per entity (array of structs), or as one
+//! column per field (struct of arrays), so that a pass over a few fields of every entity only has to
+//! read those fields.
+
+use std::borrow::Cow;
+use rayon::prelude::*;
+use crate::action::Outcome;
+use crate::behaviour::Behaviour;
+use crate::entity::{Entity, EntityMut, EntityRef};
+use crate::execution;
+use crate::world::{Direction, EntityId, Location};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntityLayout {
+    /// A Vec of Entity.
+    Aos,
+    /// An EntityColumns.
+    Soa,
+}
+
+impl EntityLayout {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntityLayout::Aos => "aos",
+            EntityLayout::Soa => "soa",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EntityLayout> {
+        match name {
+            "aos" => Some(EntityLayout::Aos),
+            "soa" => Some(EntityLayout::Soa),
+            _ => None,
+        }
+    }
+}
+
+/// The entities of a world, in order of their ids, in either layout.
+/// Passes that matter for speed match on the layout; everything else goes through the methods here.
+#[derive(Clone)]
+pub enum EntityStorage {
+    Aos(Vec<Entity>),
+    Soa(EntityColumns),
+}
+
+impl EntityStorage {
+    pub fn new(layout: EntityLayout) -> EntityStorage {
+        match layout {
+            EntityLayout::Aos => EntityStorage::Aos(Vec::new()),
+            EntityLayout::Soa => EntityStorage::Soa(EntityColumns::new()),
+        }
+    }
+
+    pub fn layout(&self) -> EntityLayout {
+        match self {
+            EntityStorage::Aos(_) => EntityLayout::Aos,
+            EntityStorage::Soa(_) => EntityLayout::Soa,
+        }
+    }
+
+    /// The same entities in the given layout.
+    pub fn into_layout(self, layout: EntityLayout) -> EntityStorage {
+        match (self, layout) {
+            (EntityStorage::Aos(entities), EntityLayout::Soa) => EntityStorage::Soa(entities.into_iter().collect()),
+            (EntityStorage::Soa(columns), EntityLayout::Aos) => EntityStorage::Aos(columns.iter().collect()),
+            (storage, _) => storage,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            EntityStorage::Aos(entities) => entities.len(),
+            EntityStorage::Soa(columns) => columns.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The entity with the given id, borrowed without gathering its fields.
+    pub fn get(&self, id: EntityId) -> Option<EntityRef<'_>> {
+        match self {
+            EntityStorage::Aos(entities) => entities.get(id).map(Entity::as_ref),
+            EntityStorage::Soa(columns) => columns.get(id),
+        }
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<EntityMut<'_>> {
+        match self {
+            EntityStorage::Aos(entities) => entities.get_mut(id).map(Entity::as_mut),
+            EntityStorage::Soa(columns) => columns.get_mut(id),
+        }
+    }
+
+    pub fn push(&mut self, entity: Entity) {
+        match self {
+            EntityStorage::Aos(entities) => entities.push(entity),
+            EntityStorage::Soa(columns) => columns.push(entity),
+        }
+    }
+
+    /// Whether the entity with the given id is still busy with an earlier action, read without gathering it.
+    pub fn is_busy(&self, id: EntityId) -> bool {
+        match self {
+            EntityStorage::Aos(entities) => entities[id].is_busy(),
+            EntityStorage::Soa(columns) => columns.cooldowns[id] > 0,
+        }
+    }
+
+    /// The locations of all entities, borrowed if they are already stored as a column.
+    pub fn locations(&self) -> Cow<'_, [Location]> {
+        match self {
+            EntityStorage::Aos(entities) => Cow::Owned(execution::map(entities, |entity| entity.location)),
+            EntityStorage::Soa(columns) => Cow::Borrowed(columns.locations()),
+        }
+    }
+}
+
+/// Entities stored as one Vec per field, all indexed by entity id.
+#[derive(Clone, Default)]
+pub struct EntityColumns {
+    locations: Vec<Location>,
+    facings: Vec<Direction>,
+    behaviours: Vec<Behaviour>,
+    lineages: Vec<u32>,
+    ages: Vec<u32>,
+    energies: Vec<f32>,
+    last_outcomes: Vec<Outcome>,
+    intervals: Vec<u8>,
+    cooldowns: Vec<u16>,
+}
+
+impl EntityColumns {
+    pub fn new() -> EntityColumns {
+        EntityColumns::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn push(&mut self, entity: Entity) {
+        self.locations.push(entity.location);
+        self.facings.push(entity.facing);
+        self.behaviours.push(entity.behaviour);
+        self.lineages.push(entity.lineage);
+        self.ages.push(entity.age);
+        self.energies.push(entity.energy);
+        self.last_outcomes.push(entity.last_outcome);
+        self.intervals.push(entity.interval);
+        self.cooldowns.push(entity.cooldown);
+    }
+
+    /// The entity with the given id, whose fields are only read from the columns once used.
+    pub fn get(&self, id: EntityId) -> Option<EntityRef<'_>> {
+        (id < self.len()).then_some(EntityRef::Columns(self, id))
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<EntityMut<'_>> {
+        (id < self.len()).then(|| EntityMut {
+            location: &mut self.locations[id],
+            facing: &mut self.facings[id],
+            behaviour: &mut self.behaviours[id],
+            lineage: &mut self.lineages[id],
+            age: &mut self.ages[id],
+            energy: &mut self.energies[id],
+            last_outcome: &mut self.last_outcomes[id],
+            interval: &mut self.intervals[id],
+            cooldown: &mut self.cooldowns[id],
+        })
+    }
+
+    pub fn locations(&self) -> &[Location] {
+        &self.locations
+    }
+
+    pub fn facings(&self) -> &[Direction] {
+        &self.facings
+    }
+
+    pub fn behaviours(&self) -> &[Behaviour] {
+        &self.behaviours
+    }
+
+    pub fn lineages(&self) -> &[u32] {
+        &self.lineages
+    }
+
+    pub fn ages(&self) -> &[u32] {
+        &self.ages
+    }
+
+    pub fn energies(&self) -> &[f32] {
+        &self.energies
+    }
+
+    pub fn last_outcomes(&self) -> &[Outcome] {
+        &self.last_outcomes
+    }
+
+    pub fn intervals(&self) -> &[u8] {
+        &self.intervals
+    }
+
+    pub fn cooldowns(&self) -> &[u16] {
+        &self.cooldowns
+    }
+
+    pub fn locations_mut(&mut self) -> &mut [Location] {
+        &mut self.locations
+    }
+
+    pub fn facings_mut(&mut self) -> &mut [Direction] {
+        &mut self.facings
+    }
+
+    pub fn behaviours_mut(&mut self) -> &mut [Behaviour] {
+        &mut self.behaviours
+    }
+
+    pub fn lineages_mut(&mut self) -> &mut [u32] {
+        &mut self.lineages
+    }
+
+    pub fn ages_mut(&mut self) -> &mut [u32] {
+        &mut self.ages
+    }
+
+    pub fn energies_mut(&mut self) -> &mut [f32] {
+        &mut self.energies
+    }
+
+    pub fn last_outcomes_mut(&mut self) -> &mut [Outcome] {
+        &mut self.last_outcomes
+    }
+
+    pub fn intervals_mut(&mut self) -> &mut [u8] {
+        &mut self.intervals
+    }
+
+    pub fn cooldowns_mut(&mut self) -> &mut [u16] {
+        &mut self.cooldowns
+    }
+
+    /// Copies of the entities, in order of their ids.
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        (0..self.len()).map(|id| self.get(id).unwrap().to_entity())
+    }
+
+    /// As iter, but in parallel.
+    pub fn par_iter(&self) -> impl IndexedParallelIterator<Item = Entity> + '_ {
+        (0..self.len()).into_par_iter().map(|id| self.get(id).unwrap().to_entity())
+    }
+
+    /// Every entity's fields, mutably, in order of their ids.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = EntityMut<'_>> {
+        self.locations.iter_mut()
+            .zip(self.facings.iter_mut())
+            .zip(self.behaviours.iter_mut())
+            .zip(self.lineages.iter_mut())
+            .zip(self.ages.iter_mut())
+            .zip(self.energies.iter_mut())
+            .zip(self.last_outcomes.iter_mut())
+            .zip(self.intervals.iter_mut())
+            .zip(self.cooldowns.iter_mut())
+            .map(|((((((((location, facing), behaviour), lineage), age), energy), last_outcome), interval), cooldown)| {
+                EntityMut { location, facing, behaviour, lineage, age, energy, last_outcome, interval, cooldown }
+            })
+    }
+
+    /// As iter_mut, but in parallel.
+    pub fn par_iter_mut(&mut self) -> impl IndexedParallelIterator<Item = EntityMut<'_>> {
+        (
+            self.locations.par_iter_mut(),
+            self.facings.par_iter_mut(),
+            self.behaviours.par_iter_mut(),
+            self.lineages.par_iter_mut(),
+            self.ages.par_iter_mut(),
+            self.energies.par_iter_mut(),
+            self.last_outcomes.par_iter_mut(),
+            self.intervals.par_iter_mut(),
+            self.cooldowns.par_iter_mut(),
+        ).into_par_iter().map(|(location, facing, behaviour, lineage, age, energy, last_outcome, interval, cooldown)| {
+            EntityMut { location, facing, behaviour, lineage, age, energy, last_outcome, interval, cooldown }
+        })
+    }
+}
+
+impl FromIterator<Entity> for EntityColumns {
+    fn from_iter<I: IntoIterator<Item = Entity>>(entities: I) -> EntityColumns {
+        let mut columns = EntityColumns::new();
+        entities.into_iter().for_each(|entity| columns.push(entity));
+        columns
+    }
+}
@@ -0,0 +1,146 @@
+//! A record of every entity's action and outcome on every tick, which together with a snapshot
+//! of the world when recording started can reconstruct the world at any later recorded tick.
+//!
+//! A recording is a directory holding:
+//! - initial.snapshot: the world when recording started (see snapshot).
+//! - events.log: for each tick, the tick number and event count, then for each event the entity id and
+//!   the action and outcome codes. Events are in the order the entities acted in, which is by id unless they
+//!   acted one after another. Entities busy with an earlier action have no events, as whether they are busy
+//!   and what that does follow from the world itself.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use crate::action::{Action, Outcome};
+use crate::binary_io::*;
+use crate::snapshot;
+use crate::world::{EntityId, World};
+
+const MAGIC: &[u8] = b"GTEVLOG2";
+const SNAPSHOT_FILE: &str = "initial.snapshot";
+const EVENTS_FILE: &str = "events.log";
+
+/// What one entity that wasn't busy did during one tick.
+#[derive(Copy, Clone, Debug)]
+pub struct Event {
+    pub entity: EntityId,
+    pub action: Action,
+    pub outcome: Outcome,
+}
+
+impl Event {
+    fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        write_u32(out, self.entity as u32)?;
+        write_u8(out, self.action.to_code())?;
+        write_u8(out, self.outcome.to_code())
+    }
+
+    fn read(input: &mut impl Read) -> Result<Event, String> {
+        let entity = read_u32(input).map_err(describe)? as EntityId;
+        let action = Action::from_code(read_u8(input).map_err(describe)?).ok_or("invalid action")?;
+        let outcome = Outcome::from_code(read_u8(input).map_err(describe)?).ok_or("invalid outcome")?;
+        Ok(Event { entity, action, outcome })
+    }
+}
+
+/// Applies a tick's events to the world, and steps the entities that were busy and so have none.
+/// Fails unless there was exactly one event for each entity that wasn't busy.
+pub fn apply_tick(world: &mut World, events: &[Event]) -> Result<(), String> {
+    let busy: Vec<bool> = (0..world.entity_count()).map(|id| world.entities().is_busy(id)).collect();
+    let mut pending = busy.iter().filter(|busy| !**busy).count();
+    let mut acted = vec![false; busy.len()];
+    for event in events {
+        match busy.get(event.entity) {
+            None => return Err(format!("there is no entity {}", event.entity)),
+            Some(true) => return Err(format!("entity {} acted while busy", event.entity)),
+            Some(false) if acted[event.entity] => return Err(format!("entity {} acted twice", event.entity)),
+            Some(false) => {}
+        }
+        world.apply_outcome(event.entity, &event.outcome)
+            .map_err(|error| format!("entity {}: {}", event.entity, error))?;
+        acted[event.entity] = true;
+        pending -= 1;
+    }
+    if pending > 0 {
+        return Err(format!("{} entities that weren't busy have no events", pending));
+    }
+    for id in (0..busy.len()).filter(|id| busy[*id]) {
+        world.apply_outcome(id, &Outcome::Busy)?;
+    }
+    Ok(())
+}
+
+pub struct EventLogWriter {
+    out: BufWriter<File>,
+}
+
+impl EventLogWriter {
+    /// Starts a recording in the given directory, creating it if needed and replacing any recording there.
+    pub fn create(dir: &str, world: &World, tick: u64) -> std::io::Result<EventLogWriter> {
+        std::fs::create_dir_all(dir)?;
+        snapshot::save(Path::new(dir).join(SNAPSHOT_FILE).to_str().unwrap(), world, tick)?;
+        let mut out = BufWriter::new(File::create(Path::new(dir).join(EVENTS_FILE))?);
+        out.write_all(MAGIC)?;
+        Ok(EventLogWriter { out })
+    }
+
+    /// Writes all events of a tick and flushes, so the log is complete even if the run is cut short.
+    pub fn write_tick(&mut self, tick: u64, events: &[Event]) -> std::io::Result<()> {
+        write_u64(&mut self.out, tick)?;
+        write_u32(&mut self.out, events.len() as u32)?;
+        for event in events {
+            event.write(&mut self.out)?;
+        }
+        self.out.flush()
+    }
+}
+
+pub struct EventLogReader {
+    input: BufReader<File>,
+}
+
+impl EventLogReader {
+    pub fn open(dir: &str) -> Result<EventLogReader, String> {
+        let path = Path::new(dir).join(EVENTS_FILE);
+        let file = File::open(&path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let mut input = BufReader::new(file);
+        expect_magic(&mut input, MAGIC)?;
+        Ok(EventLogReader { input })
+    }
+
+    /// Reads the next tick's events, or None at the end of the log.
+    pub fn next_tick(&mut self) -> Result<Option<(u64, Vec<Event>)>, String> {
+        let tick = match read_u64(&mut self.input) {
+            Ok(tick) => tick,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error.to_string()),
+        };
+        let count = read_u32(&mut self.input).map_err(describe)?;
+        let events = (0..count)
+            .map(|_| Event::read(&mut self.input))
+            .collect::<Result<Vec<Event>, String>>()
+            .map_err(|error| format!("tick {}: {}", tick, error))?;
+        Ok(Some((tick, events)))
+    }
+}
+
+/// Loads the recording's initial snapshot, the tick it was taken at, and a reader for its events.
+pub fn open_recording(dir: &str) -> Result<(World, u64, EventLogReader), String> {
+    let (world, tick) = snapshot::load(Path::new(dir).join(SNAPSHOT_FILE).to_str().unwrap())?;
+    Ok((world, tick, EventLogReader::open(dir)?))
+}
+
+/// Reconstructs the world as it was at the start of the given tick, or at the end of the recording if sooner.
+/// Returns the world and the tick it was reconstructed for.
+pub fn replay(dir: &str, until_tick: Option<u64>) -> Result<(World, u64), String> {
+    let (mut world, mut tick, mut reader) = open_recording(dir)?;
+    while until_tick.is_none_or(|until_tick| tick < until_tick) {
+        let Some((event_tick, events)) = reader.next_tick()? else { break };
+        if event_tick != tick {
+            return Err(format!("expected events for tick {} but found tick {}", tick, event_tick));
+        }
+        apply_tick(&mut world, &events).map_err(|error| format!("tick {}: {}", tick, error))?;
+        tick += 1;
+    }
+    Ok((world, tick))
+}
@@ -0,0 +1,158 @@
+//! How the world processor runs its phases: in parallel on a thread pool it owns, or sequentially on
+//! the calling thread, for debugging and for checking that results don't depend on scheduling.
+//!
+//! The helpers here run a function over each item of a slice in whichever way is current.
+
+use std::ops::Add;
+use std::sync::{Arc, Mutex};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Execution {
+    /// On a pool of the given number of threads.
+    Parallel(usize),
+    Sequential,
+}
+
+/// The execution set, if any, and the pool for it once built. Locked so that the first phases to run, on the
+/// processor's thread and the render worker at once, build the default pool only once.
+struct State {
+    execution: Option<Execution>,
+    pool: Option<Arc<ThreadPool>>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State { execution: None, pool: None });
+
+/// Parallel on one thread per CPU, unless set otherwise.
+pub fn current() -> Execution {
+    STATE.lock().unwrap().execution.unwrap_or_else(default_execution)
+}
+
+fn default_execution() -> Execution {
+    Execution::Parallel(std::thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+/// Anything already running on the old pool finishes there.
+pub fn set(execution: Execution) -> Result<(), String> {
+    let pool = match execution {
+        Execution::Parallel(0) => return Err(String::from("there must be at least one thread")),
+        Execution::Parallel(threads) => Some(Arc::new(build_pool(threads)?)),
+        Execution::Sequential => None,
+    };
+    *STATE.lock().unwrap() = State { execution: Some(execution), pool };
+    Ok(())
+}
+
+fn build_pool(threads: usize) -> Result<ThreadPool, String> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("processor-{}", i))
+        .build()
+        .map_err(|error| error.to_string())
+}
+
+pub fn is_sequential() -> bool {
+    current() == Execution::Sequential
+}
+
+/// Runs op on the pool, building the default pool if none has been set, or on this thread if sequential.
+pub fn install<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    let pool = {
+        let mut state = STATE.lock().unwrap();
+        let execution = state.execution.unwrap_or_else(default_execution);
+        let Execution::Parallel(threads) = execution else {
+            drop(state);
+            return op();
+        };
+        // The lock is released before running op, so that op can install further work on the same pool.
+        state.pool.get_or_insert_with(|| {
+            Arc::new(build_pool(threads).expect("should be able to build thread pool"))
+        }).clone()
+    };
+    pool.install(op)
+}
+
+pub fn for_each<T: Sync>(items: &[T], f: impl Fn(&T) + Sync + Send) {
+    if is_sequential() {
+        items.iter().for_each(f);
+    } else {
+        install(|| items.par_iter().for_each(f));
+    }
+}
+
+/// As for_each, with the index of each item.
+pub fn for_each_indexed<T: Sync>(items: &[T], f: impl Fn(usize, &T) + Sync + Send) {
+    if is_sequential() {
+        items.iter().enumerate().for_each(|(i, item)| f(i, item));
+    } else {
+        install(|| items.par_iter().enumerate().for_each(|(i, item)| f(i, item)));
+    }
+}
+
+/// As for_each_mut, with the index of each item.
+pub fn for_each_indexed_mut<T: Send>(items: &mut [T], f: impl Fn(usize, &mut T) + Sync + Send) {
+    if is_sequential() {
+        items.iter_mut().enumerate().for_each(|(i, item)| f(i, item));
+    } else {
+        install(|| items.par_iter_mut().enumerate().for_each(|(i, item)| f(i, item)));
+    }
+}
+
+pub fn for_each_mut<T: Send>(items: &mut [T], f: impl Fn(&mut T) + Sync + Send) {
+    if is_sequential() {
+        items.iter_mut().for_each(f);
+    } else {
+        install(|| items.par_iter_mut().for_each(f));
+    }
+}
+
+/// The results of f for each item, in the order of the items.
+pub fn map<T: Sync, U: Send>(items: &[T], f: impl Fn(&T) -> U + Sync + Send) -> Vec<U> {
+    if is_sequential() {
+        items.iter().map(f).collect()
+    } else {
+        install(|| items.par_iter().map(f).collect())
+    }
+}
+
+/// As map, with the index of each item.
+pub fn map_indexed<T: Sync, U: Send>(items: &[T], f: impl Fn(usize, &T) -> U + Sync + Send) -> Vec<U> {
+    if is_sequential() {
+        items.iter().enumerate().map(|(i, item)| f(i, item)).collect()
+    } else {
+        install(|| items.par_iter().enumerate().map(|(i, item)| f(i, item)).collect())
+    }
+}
+
+/// Combines the results of f for each item with op, which must be associative and have identity as its identity.
+pub fn reduce<T: Sync, U: Send + Copy + Sync>(
+    items: &[T],
+    identity: U,
+    f: impl Fn(&T) -> U + Sync + Send,
+    op: impl Fn(U, U) -> U + Sync + Send,
+) -> U {
+    if is_sequential() {
+        items.iter().map(f).fold(identity, op)
+    } else {
+        install(|| items.par_iter().map(f).reduce(|| identity, op))
+    }
+}
+
+/// The sum of the results of f for each item.
+pub fn sum<T: Sync, U: Send + Default + Add<Output = U>>(items: &[T], f: impl Fn(&T) -> U + Sync + Send) -> U {
+    if is_sequential() {
+        items.iter().map(f).fold(U::default(), |a, b| a + b)
+    } else {
+        install(|| items.par_iter().map(f).reduce(U::default, |a, b| a + b))
+    }
+}
+
+/// As sum, with the index of each item.
+pub fn sum_indexed<T: Sync, U: Send + Default + Add<Output = U>>(items: &[T], f: impl Fn(usize, &T) -> U + Sync + Send) -> U {
+    if is_sequential() {
+        items.iter().enumerate().map(|(i, item)| f(i, item)).fold(U::default(), |a, b| a + b)
+    } else {
+        install(|| items.par_iter().enumerate().map(|(i, item)| f(i, item)).reduce(U::default, |a, b| a + b))
+    }
+}
@@ -1,10 +1,15 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+const DEFAULT_WINDOW: usize = 32;
 
 pub struct FpsCounter {
-    frame_time_buffer: [u128; 32],
+    frame_time_buffer: Vec<Duration>,
     current_frame: usize,
+    recorded_frames: usize,
+    total_frames: u64,
     current_frame_start: Instant,
-    mode: Mode
+    mode: Mode,
+    name: &'static str,
 }
 
 pub enum Mode {
@@ -15,6 +20,58 @@ pub enum Mode {
     Every32Frames
 }
 
+/// Summary of the frame times in an FpsCounter's window.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FrameStats {
+    /// How many frames the statistics were taken over.
+    pub frames: usize,
+    pub fps: f64,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+impl FrameStats {
+    /// Takes statistics over the given frame times, which are sorted in place.
+    pub fn from_frame_times(frame_times: &mut [Duration]) -> FrameStats {
+        if frame_times.is_empty() { return FrameStats::default() }
+
+        frame_times.sort_unstable();
+        let total: Duration = frame_times.iter().sum();
+        let mean = total / frame_times.len() as u32;
+        let fps = if total.is_zero() { 0.0 } else { frame_times.len() as f64 / total.as_secs_f64() };
+        FrameStats {
+            frames: frame_times.len(),
+            fps,
+            min: frame_times[0],
+            max: frame_times[frame_times.len() - 1],
+            mean,
+            p50: percentile(frame_times, 50),
+            p95: percentile(frame_times, 95),
+            p99: percentile(frame_times, 99),
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl Default for FpsCounter {
+    fn default() -> FpsCounter {
+        FpsCounter::new()
+    }
+}
+
 impl FpsCounter {
     #[allow(unused)]
     pub fn every_4_frames() -> FpsCounter {
@@ -44,13 +101,31 @@ impl FpsCounter {
     #[allow(unused)]
     pub fn with_mode(mode: Mode) -> FpsCounter {
         FpsCounter {
-            frame_time_buffer: [0_u128; 32],
+            frame_time_buffer: vec![Duration::ZERO; DEFAULT_WINDOW],
             current_frame: 0,
+            recorded_frames: 0,
+            total_frames: 0,
             current_frame_start: Instant::now(),
             mode,
+            name: "fps",
         }
     }
 
+    /// Sets the name used when printing, e.g. "tps" for a counter of simulation ticks.
+    pub fn named(mut self, name: &'static str) -> FpsCounter {
+        self.name = name;
+        self
+    }
+
+    /// Sets how many of the most recent frames statistics are taken over, discarding any recorded so far.
+    #[allow(unused)]
+    pub fn with_window(mut self, window: usize) -> FpsCounter {
+        self.frame_time_buffer = vec![Duration::ZERO; window.max(1)];
+        self.current_frame = 0;
+        self.recorded_frames = 0;
+        self
+    }
+
     fn print_fps(&self) {
         let frame_count = match self.mode {
             Mode::Off => return,
@@ -59,16 +134,29 @@ impl FpsCounter {
             Mode::Every16Frames => 16,
             Mode::Every32Frames => 32,
         };
-        if self.current_frame % frame_count != 0 { return; }
+        if self.total_frames % frame_count != 0 { return; }
 
-        let mut total_millis = 0;
-        for i in 1..=frame_count {
-            let frame = (self.current_frame + 32 - i) % 32;
-            total_millis += self.frame_time_buffer[frame];
-        }
-        let average_frame_time_millis = total_millis / frame_count as u128;
-        let fps = 1000_u128.checked_div(average_frame_time_millis).unwrap_or(0);
-        println!("fps: {} (frame-time: {} ms)", fps, average_frame_time_millis);
+        let stats = self.stats_over(frame_count as usize);
+        println!(
+            "{}: {:.1} (frame-time over {} frames: mean {:.3} ms, min {:.3} ms, p50 {:.3} ms, p95 {:.3} ms, p99 {:.3} ms, max {:.3} ms)",
+            self.name, stats.fps, stats.frames, millis(stats.mean), millis(stats.min),
+            millis(stats.p50), millis(stats.p95), millis(stats.p99), millis(stats.max),
+        );
+    }
+
+    /// Statistics over all frames in the window.
+    pub fn stats(&self) -> FrameStats {
+        self.stats_over(self.frame_time_buffer.len())
+    }
+
+    /// Statistics over the most recent frames, up to the window length.
+    pub fn stats_over(&self, frame_count: usize) -> FrameStats {
+        let window = self.frame_time_buffer.len();
+        let frame_count = frame_count.min(self.recorded_frames);
+        let mut frame_times: Vec<Duration> = (1..=frame_count)
+            .map(|i| self.frame_time_buffer[(self.current_frame + window - i) % window])
+            .collect();
+        FrameStats::from_frame_times(&mut frame_times)
     }
 
     #[allow(unused)]
@@ -77,9 +165,12 @@ impl FpsCounter {
     }
 
     pub fn tick(&mut self) {
-        self.frame_time_buffer[self.current_frame] = self.current_frame_start.elapsed().as_millis();
+        let window = self.frame_time_buffer.len();
+        self.frame_time_buffer[self.current_frame] = self.current_frame_start.elapsed();
         self.current_frame_start = Instant::now();
-        self.current_frame = (self.current_frame + 1) % 32;
+        self.current_frame = (self.current_frame + 1) % window;
+        self.recorded_frames = (self.recorded_frames + 1).min(window);
+        self.total_frames += 1;
         self.print_fps();
     }
-}
\ No newline at end of file
+}
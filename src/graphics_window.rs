@@ -1,12 +1,22 @@
+use std::ptr::addr_of;
+use std::sync::atomic::{AtomicU32, Ordering};
 use softbuffer::GraphicsContext;
 use winit::dpi::PhysicalSize;
 use winit::event_loop::EventLoop;
 use winit::window::{Window, WindowBuilder};
+use crate::bitmap_font;
 
-static mut PIXEL_BUFFER: Vec<u32> = Vec::new();
+/// Pixels are atomic as entities are drawn from many threads at once.
+/// Relaxed ordering is enough, as the buffer is only shown after drawing has been joined.
+static mut PIXEL_BUFFER: Vec<AtomicU32> = Vec::new();
 static mut WIDTH: usize = 0;
 static mut HEIGHT: usize = 0;
 
+/// Safety: The buffer must not be being replaced, which only happens when the window is built.
+unsafe fn pixel_buffer() -> &'static [AtomicU32] {
+    &*addr_of!(PIXEL_BUFFER)
+}
+
 pub fn width() -> usize {
     unsafe { WIDTH }
 }
@@ -27,7 +37,7 @@ impl WindowConfig {
         window.set_title(self.title.as_str());
         window.set_resizable(self.resizable);
         window.set_inner_size(PhysicalSize::new(self.width, self.height));
-        return window;
+        window
     }
 }
 
@@ -39,7 +49,7 @@ pub struct GraphicsWindow {
 fn build_window(event_loop: &EventLoop<()>, config: &WindowConfig) -> Window {
     config.apply(
         WindowBuilder::new()
-            .build(&event_loop)
+            .build(event_loop)
             .expect("should be able to build a window")
     )
 }
@@ -49,7 +59,7 @@ pub fn build_graphics_window(config: WindowConfig) -> (GraphicsWindow, EventLoop
     let window = build_window(&event_loop, &config);
     let buffer_size = config.width * config.height;
     unsafe {
-        PIXEL_BUFFER = vec![0; buffer_size as usize];
+        PIXEL_BUFFER = (0..buffer_size).map(|_| AtomicU32::new(0)).collect();
         WIDTH = config.width as usize;
         HEIGHT = config.height as usize;
     }
@@ -57,7 +67,7 @@ pub fn build_graphics_window(config: WindowConfig) -> (GraphicsWindow, EventLoop
         graphics_context: unsafe { GraphicsContext::new(&window, &window) }.unwrap(),
         window,
     };
-    return (graphics_window, event_loop);
+    (graphics_window, event_loop)
 }
 
 impl GraphicsWindow {
@@ -74,7 +84,7 @@ pub fn clear(color: Color) {
     //self.pixel_buffer.iter_mut().for_each(|px| *px = 0);
 
     unsafe {
-        PIXEL_BUFFER.set_all(color.0);
+        pixel_buffer().iter().for_each(|pixel| pixel.store(color.0, Ordering::Relaxed));
     }
 
     //self.pixel_buffer[index as usize] = color.to_u32();
@@ -83,33 +93,72 @@ pub fn clear(color: Color) {
 pub fn draw_pixel(x: usize, y: usize, color: Color) {
     let index = x + width() * y;
     unsafe {
-        PIXEL_BUFFER[index] = color.0;
+        pixel_buffer()[index].store(color.0, Ordering::Relaxed);
     }
 }
 
-fn redraw(graphics_context: &mut GraphicsContext) {
-    graphics_context.set_buffer(unsafe { &PIXEL_BUFFER }, width() as u16, height() as u16);
+/// Mixes the colour into the pixel already at (x, y), where an opacity of 1 replaces it entirely.
+pub fn blend_pixel(x: usize, y: usize, color: Color, opacity: f32) {
+    let index = x + width() * y;
+    unsafe {
+        let pixel = &pixel_buffer()[index];
+        pixel.store(Color(pixel.load(Ordering::Relaxed)).lerp(color, opacity).0, Ordering::Relaxed);
+    }
 }
 
+/// Fills the given rectangle, clipped to the window.
+pub fn fill_rect(x: usize, y: usize, width: usize, height: usize, color: Color) {
+    let x_end = (x + width).min(self::width());
+    let y_end = (y + height).min(self::height());
+    for y in y..y_end {
+        for x in x..x_end {
+            draw_pixel(x, y, color);
+        }
+    }
+}
+
+/// Returns the width in pixels of the given text when drawn with draw_text.
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * (bitmap_font::GLYPH_WIDTH + 1)
+}
 
-/*************************************************************/
-// Allows calling .set_all(some_value) on an array to set all values in the array to some_value.
-// Taken from https://stackoverflow.com/a/49193323/17816986
-trait SetAll {
-    type Elem;
-    fn set_all(&mut self, value: Self::Elem);
+/// Returns the height in pixels of a line of text when drawn with draw_text.
+pub fn text_height() -> usize {
+    bitmap_font::GLYPH_HEIGHT + 1
 }
 
-impl<T> SetAll for [T] where T: Clone {
-    type Elem = T;
-    fn set_all(&mut self, value: Self::Elem) {
-        for e in self {
-            *e = value.clone();
+/// Draws text with its top-left corner at (x, y), clipped to the window.
+/// Only the set pixels of each glyph are drawn, so text can be placed over any background.
+pub fn draw_text(x: usize, y: usize, text: &str, color: Color) {
+    let mut glyph_x = x;
+    for c in text.chars() {
+        if glyph_x >= width() { break }
+        let glyph = bitmap_font::glyph(c);
+        for column in 0..bitmap_font::GLYPH_WIDTH {
+            for row in 0..bitmap_font::GLYPH_HEIGHT {
+                let (px, py) = (glyph_x + column, y + row);
+                if px < width() && py < height() && bitmap_font::is_set(glyph, column, row) {
+                    draw_pixel(px, py, color);
+                }
+            }
         }
+        glyph_x += bitmap_font::GLYPH_WIDTH + 1;
     }
 }
-/*************************************************************/
 
+fn redraw(graphics_context: &mut GraphicsContext) {
+    // Safety: AtomicU32 has the same size as u32 and at least its alignment,
+    // and nothing is drawing while the buffer is being shown.
+    let pixels = unsafe {
+        let buffer = pixel_buffer();
+        std::slice::from_raw_parts(buffer.as_ptr() as *const u32, buffer.len())
+    };
+    graphics_context.set_buffer(pixels, width() as u16, height() as u16);
+}
+
+
+
+#[derive(Copy, Clone)]
 pub struct Color(u32);
 
 impl Color {
@@ -127,6 +176,20 @@ impl Color {
     pub const fn new(r: u8, g: u8, b: u8) -> Color {
         Color(rgb_to_u32(r, g, b))
     }
+
+    pub const fn r(&self) -> u8 { (self.0 >> 16) as u8 }
+
+    pub const fn g(&self) -> u8 { (self.0 >> 8) as u8 }
+
+    pub const fn b(&self) -> u8 { self.0 as u8 }
+
+    /// Linearly interpolates from this colour (t = 0) to the other colour (t = 1).
+    /// Values of t outside 0 to 1 are clamped.
+    pub fn lerp(&self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color::new(mix(self.r(), other.r()), mix(self.g(), other.g()), mix(self.b(), other.b()))
+    }
 }
 
 const fn rgb_to_u32(r: u8, g: u8, b: u8) -> u32 {
@@ -0,0 +1,236 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::{execution, graphics_window};
+use crate::chunked_grid::ChunkedGrid;
+use crate::graphics_window::Color;
+use crate::topology::Topology;
+use crate::world::Location;
+
+const GRADIENT: [Color; 5] = [
+    Color::new(0, 0, 0),
+    Color::new(0, 0, 255),
+    Color::new(255, 0, 0),
+    Color::new(255, 255, 0),
+    Color::new(255, 255, 255),
+];
+
+/// How strongly the heatmap is blended over the entity view in OverlayMode::Over.
+const OVERLAY_OPACITY: f32 = 0.75;
+/// Chunks whose values have all decayed below this are freed once nothing happens in them.
+const FADED: f32 = 0.01;
+
+/// Which per-cell statistic a heatmap shows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HeatmapKind {
+    /// How often the cell was occupied at the end of a tick.
+    Occupancy,
+    /// How often a move into the cell was blocked by a conflict.
+    Blocking,
+    /// How many entities moved into the cell.
+    Traffic,
+}
+
+impl HeatmapKind {
+    pub fn next(&self) -> HeatmapKind {
+        match self {
+            HeatmapKind::Occupancy => HeatmapKind::Blocking,
+            HeatmapKind::Blocking => HeatmapKind::Traffic,
+            HeatmapKind::Traffic => HeatmapKind::Occupancy,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HeatmapKind::Occupancy => "occupancy",
+            HeatmapKind::Blocking => "blocking",
+            HeatmapKind::Traffic => "traffic",
+        }
+    }
+}
+
+/// How a heatmap is combined with the entity view.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverlayMode {
+    Off,
+    /// Blended on top of the entities.
+    Over,
+    /// Drawn instead of the entities.
+    Only,
+}
+
+impl OverlayMode {
+    pub fn next(&self) -> OverlayMode {
+        match self {
+            OverlayMode::Off => OverlayMode::Over,
+            OverlayMode::Over => OverlayMode::Only,
+            OverlayMode::Only => OverlayMode::Off,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverlayMode::Off => "off",
+            OverlayMode::Over => "over",
+            OverlayMode::Only => "only",
+        }
+    }
+}
+
+/// One cell's value, which can be added to from many threads at once.
+#[derive(Default)]
+struct HeatCell(AtomicU32);
+
+impl HeatCell {
+    fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    fn add(&self, amount: f32) {
+        self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f32::from_bits(bits) + amount).to_bits()))
+            .unwrap();
+    }
+}
+
+/// Accumulating per-cell statistics, each multiplied by the decay factor once per tick.
+/// Cells may only be added to once their chunk has been allocated. Cells in chunks that have never been
+/// allocated are zero.
+pub struct Heatmap {
+    width: usize,
+    height: usize,
+    topology: Topology,
+    decay: f32,
+    occupancy: ChunkedGrid<HeatCell>,
+    blocking: ChunkedGrid<HeatCell>,
+    traffic: ChunkedGrid<HeatCell>,
+    kind: HeatmapKind,
+    overlay: OverlayMode,
+}
+
+impl Heatmap {
+    getter!(kind: HeatmapKind);
+    getter!(overlay: OverlayMode);
+    getter!(decay: f32);
+
+    pub fn new(width: usize, height: usize, topology: Topology, decay: f32) -> Heatmap {
+        Heatmap {
+            width,
+            height,
+            topology,
+            decay,
+            occupancy: ChunkedGrid::new(width, height),
+            blocking: ChunkedGrid::new(width, height),
+            traffic: ChunkedGrid::new(width, height),
+            kind: HeatmapKind::Occupancy,
+            overlay: OverlayMode::Off,
+        }
+    }
+
+    pub fn set_kind(&mut self, kind: HeatmapKind) {
+        self.kind = kind;
+    }
+
+    pub fn set_overlay(&mut self, overlay: OverlayMode) {
+        self.overlay = overlay;
+    }
+
+    /// Sets what each cell is multiplied by every tick, from 0 to forget everything at once to 1 to never forget.
+    pub fn set_decay(&mut self, decay: f32) {
+        self.decay = decay;
+    }
+
+    pub fn reset(&mut self) {
+        self.occupancy.clear();
+        self.blocking.clear();
+        self.traffic.clear();
+    }
+
+    /// Allocates the given chunks, so that what happens in them can be recorded.
+    pub fn allocate(&mut self, chunks: &[usize]) {
+        for chunk in chunks {
+            self.occupancy.allocate(*chunk);
+            self.blocking.allocate(*chunk);
+            self.traffic.allocate(*chunk);
+        }
+    }
+
+    /// Decays every allocated cell, so that the cost depends on how much of the world has been visited
+    /// recently rather than its size. Chunks outside the given active chunks, which must be in index order,
+    /// are freed once they have faded.
+    pub fn apply_decay(&mut self, active_chunks: &[usize]) {
+        if self.decay >= 1.0 { return }
+        let decay = self.decay;
+        for values in [&mut self.occupancy, &mut self.blocking, &mut self.traffic] {
+            let faded = execution::map(&values.allocated_chunks(), |(chunk, cells)| {
+                cells.iter().for_each(|cell| cell.set(cell.get() * decay));
+                let faded = active_chunks.binary_search(chunk).is_err() && cells.iter().all(|cell| cell.get() < FADED);
+                faded.then_some(*chunk)
+            });
+            faded.into_iter().flatten().for_each(|chunk| values.free(chunk));
+        }
+    }
+
+    pub fn add_occupancy(&self, location: &Location) {
+        Self::cell(&self.occupancy, location).add(1.0);
+    }
+
+    pub fn add_blocking(&self, location: &Location, count: usize) {
+        Self::cell(&self.blocking, location).add(count as f32);
+    }
+
+    pub fn add_traffic(&self, location: &Location) {
+        Self::cell(&self.traffic, location).add(1.0);
+    }
+
+    fn cell<'a>(values: &'a ChunkedGrid<HeatCell>, location: &Location) -> &'a HeatCell {
+        values.get_allocated(location.x(), location.y())
+            .expect("heatmap chunks should be allocated before recording in them")
+    }
+
+    fn values(&self) -> &ChunkedGrid<HeatCell> {
+        match self.kind {
+            HeatmapKind::Occupancy => &self.occupancy,
+            HeatmapKind::Blocking => &self.blocking,
+            HeatmapKind::Traffic => &self.traffic,
+        }
+    }
+
+    /// Draws the selected statistic according to the overlay mode, scaled so the hottest cell is white.
+    pub fn draw(&self) {
+        if self.overlay == OverlayMode::Off { return }
+
+        let values = self.values();
+        let max = execution::reduce(&values.allocated_chunks(), 0.0, |(_, cells)| {
+            cells.iter().map(HeatCell::get).fold(0.0, f32::max)
+        }, f32::max);
+        if max <= 0.0 { return }
+
+        let topology = self.topology;
+        let (columns, rows) = topology.cells_within(graphics_window::width(), graphics_window::height());
+        let (width, height) = (self.width.min(columns), self.height.min(rows));
+        let overlay = self.overlay;
+        let rows: Vec<usize> = (0..height).collect();
+        execution::for_each(&rows, |&y| {
+            for x in 0..width {
+                let value = values.get(x, y).get();
+                match overlay {
+                    OverlayMode::Off => {}
+                    OverlayMode::Only => topology.draw_cell(x, y, heat_color(value / max)),
+                    OverlayMode::Over => if value > 0.0 {
+                        topology.blend_cell(x, y, heat_color(value / max), OVERLAY_OPACITY);
+                    },
+                }
+            }
+        });
+    }
+}
+
+/// Maps a value from 0 to 1 onto the heat gradient.
+/// The square root is taken first so that rarely visited cells are still visible.
+fn heat_color(t: f32) -> Color {
+    let position = t.clamp(0.0, 1.0).sqrt() * (GRADIENT.len() - 1) as f32;
+    let index = (position as usize).min(GRADIENT.len() - 2);
+    GRADIENT[index].lerp(GRADIENT[index + 1], position - index as f32)
+}
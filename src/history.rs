@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use crate::action::Outcome;
+use crate::world::World;
+
+/// The outcome of every entity during one tick, indexed by entity id and stored as outcome codes.
+pub struct TickDelta {
+    outcomes: Vec<u8>,
+    /// The ids of the entities in the order they acted, or empty if they acted together.
+    order: Vec<u32>,
+}
+
+impl TickDelta {
+    pub fn new(outcomes: Vec<u8>, order: Vec<u32>) -> TickDelta {
+        TickDelta { outcomes, order }
+    }
+
+    fn outcome_of(&self, id: usize) -> Outcome {
+        Outcome::from_code(self.outcomes[id]).expect("history should only hold valid outcome codes")
+    }
+
+    /// Advances the world by the tick this delta was recorded for.
+    fn apply(&self, world: &mut World) {
+        assert_eq!(self.outcomes.len(), world.entity_count(), "history should match the world's entities");
+        if !self.order.is_empty() {
+            for id in &self.order {
+                let id = *id as usize;
+                world.apply_outcome(id, &self.outcome_of(id)).expect("history should only move entities into empty cells");
+            }
+            return;
+        }
+        // Safety: The outcomes were recorded from a step of this same world, where moves were only into
+        // unoccupied cells, one mover per cell.
+        unsafe {
+            world.apply_outcomes(|id| self.outcome_of(id));
+        }
+    }
+}
+
+/// How many ticks apart the copies of the world kept alongside the keyframe are, which bounds how many deltas
+/// reconstructing a tick takes.
+const CHECKPOINT_INTERVAL: u64 = 32;
+
+/// The most recent ticks of a world, kept as a keyframe of the earliest tick plus a delta for each tick since.
+/// Copies of the world every CHECKPOINT_INTERVAL ticks are kept too, so that stepping back through the history
+/// only replays the deltas since the nearest one rather than since the keyframe.
+pub struct History {
+    capacity: usize,
+    keyframe: World,
+    keyframe_tick: u64,
+    deltas: VecDeque<TickDelta>,
+    /// In order of tick, all after the keyframe and no later than the latest tick.
+    checkpoints: VecDeque<(u64, World)>,
+}
+
+impl History {
+    /// Starts a history at the given world and tick, keeping at most capacity ticks before the latest.
+    pub fn new(world: &World, tick: u64, capacity: usize) -> History {
+        History {
+            capacity: capacity.max(1),
+            keyframe: world.clone(),
+            keyframe_tick: tick,
+            deltas: VecDeque::with_capacity(capacity.max(1)),
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    pub fn earliest_tick(&self) -> u64 {
+        self.keyframe_tick
+    }
+
+    pub fn latest_tick(&self) -> u64 {
+        self.keyframe_tick + self.deltas.len() as u64
+    }
+
+    pub fn contains(&self, tick: u64) -> bool {
+        (self.earliest_tick()..=self.latest_tick()).contains(&tick)
+    }
+
+    /// Adds the delta that advances the latest tick by one, moving the keyframe forward if at capacity.
+    pub fn push(&mut self, delta: TickDelta) {
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front().unwrap().apply(&mut self.keyframe);
+            self.keyframe_tick += 1;
+            if self.checkpoints.front().is_some_and(|(tick, _)| *tick == self.keyframe_tick) {
+                self.checkpoints.pop_front();
+            }
+        }
+        self.deltas.push_back(delta);
+    }
+
+    /// Keeps a copy of the world at the latest tick if one is due, which should be offered after every push.
+    pub fn offer_checkpoint(&mut self, world: &World, tick: u64) {
+        if tick == self.latest_tick() && tick > self.keyframe_tick && tick % CHECKPOINT_INTERVAL == 0 {
+            self.checkpoints.push_back((tick, world.clone()));
+        }
+    }
+
+    /// Forgets every tick after the given one, so that a new branch can be recorded from it.
+    pub fn truncate_after(&mut self, tick: u64) {
+        let keep = tick.saturating_sub(self.keyframe_tick) as usize;
+        self.deltas.truncate(keep);
+        while self.checkpoints.back().is_some_and(|(checkpoint_tick, _)| *checkpoint_tick > tick) {
+            self.checkpoints.pop_back();
+        }
+    }
+
+    /// Reconstructs the world at the given tick from the nearest copy before it, or None if it is not in the history.
+    pub fn world_at(&self, tick: u64) -> Option<World> {
+        if !self.contains(tick) { return None }
+        let (start_tick, start) = self.checkpoints.iter().rev()
+            .find(|(checkpoint_tick, _)| *checkpoint_tick <= tick)
+            .map_or((self.keyframe_tick, &self.keyframe), |(checkpoint_tick, world)| (*checkpoint_tick, world));
+        let mut world = start.clone();
+        let skip = (start_tick - self.keyframe_tick) as usize;
+        for delta in self.deltas.iter().skip(skip).take((tick - start_tick) as usize) {
+            delta.apply(&mut world);
+        }
+        Some(world)
+    }
+
+    /// Advances a world at the given tick by one recorded tick.
+    /// Returns false, leaving the world unchanged, if the next tick is not in the history.
+    pub fn step_forward(&self, world: &mut World, tick: u64) -> bool {
+        if tick < self.keyframe_tick || tick >= self.latest_tick() { return false }
+        self.deltas[(tick - self.keyframe_tick) as usize].apply(world);
+        true
+    }
+}
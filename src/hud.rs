@@ -0,0 +1,115 @@
+use std::time::Duration;
+use crate::color_mode::ColorMode;
+use crate::entity::Entity;
+use crate::fps_counter::FrameStats;
+use crate::graphics_window;
+use crate::graphics_window::Color;
+use crate::heatmap::{HeatmapKind, OverlayMode};
+use crate::profiler::Phase;
+use crate::topology::Topology;
+use crate::update_scheme::UpdateScheme;
+
+const MARGIN: usize = 4;
+const TEXT_COLOR: Color = Color::WHITE;
+const BACKGROUND_COLOR: Color = Color::new(32, 32, 32);
+const MARKER_COLOR: Color = Color::new(255, 0, 255);
+/// Distance from the inspected entity to each side of the square drawn around it.
+const MARKER_RADIUS: usize = 4;
+
+/// The values shown by the HUD, gathered fresh each frame.
+pub struct HudInfo {
+    pub fps: FrameStats,
+    pub tps: FrameStats,
+    pub tick: u64,
+    pub entity_count: usize,
+    /// Mean duration of each phase over the profiler's window.
+    pub phase_means: Vec<(Phase, Duration)>,
+    pub paused: bool,
+    /// How the world's cells are laid out in the window, for marking the inspected entity.
+    pub topology: Topology,
+    pub update_scheme: UpdateScheme,
+    pub color_mode: ColorMode,
+    pub heatmap_kind: HeatmapKind,
+    pub heatmap_overlay: OverlayMode,
+    /// The earliest and latest ticks that can be rewound to.
+    pub history: Option<(u64, u64)>,
+    /// The entity selected for inspection, with its id.
+    pub inspected: Option<(usize, Entity)>,
+}
+
+pub struct Hud {
+    visible: bool,
+}
+
+impl Hud {
+    pub fn new(visible: bool) -> Hud {
+        Hud { visible }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Draws the HUD over whatever is currently in the pixel buffer.
+    /// The inspected entity is marked even while the rest of the HUD is hidden.
+    pub fn draw(&self, info: &HudInfo) {
+        if let Some((_, entity)) = &info.inspected {
+            let (x, y) = info.topology.cell_center(entity.location.x(), entity.location.y());
+            draw_marker(x, y);
+        }
+        if !self.visible { return }
+
+        let lines = Self::lines(info);
+        let width = lines.iter().map(|line| graphics_window::text_width(line)).max().unwrap_or(0);
+        let height = lines.len() * graphics_window::text_height();
+        graphics_window::fill_rect(0, 0, width + 2 * MARGIN, height + 2 * MARGIN, BACKGROUND_COLOR);
+
+        for (i, line) in lines.iter().enumerate() {
+            let y = MARGIN + i * graphics_window::text_height();
+            graphics_window::draw_text(MARGIN, y, line, TEXT_COLOR);
+        }
+    }
+
+    fn lines(info: &HudInfo) -> Vec<String> {
+        let mut lines = vec![
+            format!("fps: {:.1} (p99 {})", info.fps.fps, format_duration(info.fps.p99)),
+            format!("tps: {:.1} (p99 {})", info.tps.fps, format_duration(info.tps.p99)),
+            format!("tick: {}{}", info.tick, if info.paused { " (paused)" } else { "" }),
+            format!("entities: {}", info.entity_count),
+            format!("update: {}", info.update_scheme.name()),
+            format!("colour: {}", info.color_mode.name()),
+            format!("heatmap: {} ({})", info.heatmap_kind.name(), info.heatmap_overlay.name()),
+        ];
+        if let Some((earliest, latest)) = info.history {
+            let viewing_past = if info.tick < latest { " (viewing past)" } else { "" };
+            lines.push(format!("history: ticks {} to {}{}", earliest, latest, viewing_past));
+        }
+        if let Some((id, entity)) = &info.inspected {
+            lines.push(format!("entity {}: ({}, {}) facing {:?}", id, entity.location.x(), entity.location.y(), entity.facing));
+            lines.push(format!("  {:?}, lineage {}", entity.behaviour, entity.lineage));
+            lines.push(format!("  age {}, energy {:.3}", entity.age, entity.energy));
+            lines.push(format!("  last outcome {:?}, busy for {} ticks", entity.last_outcome, entity.cooldown));
+            lines.push(format!("  acts every {} ticks", entity.interval));
+        }
+        for (phase, mean) in &info.phase_means {
+            let indent = if *phase == Phase::Step { "" } else { "  " };
+            lines.push(format!("{}{}: {}", indent, phase.name(), format_duration(*mean)));
+        }
+        lines
+    }
+}
+
+/// Draws a square outline centred on the given pixel.
+fn draw_marker(x: usize, y: usize) {
+    let left = x.saturating_sub(MARKER_RADIUS);
+    let top = y.saturating_sub(MARKER_RADIUS);
+    let size = 2 * MARKER_RADIUS + 1;
+    graphics_window::fill_rect(left, top, size, 1, MARKER_COLOR);
+    graphics_window::fill_rect(left, y + MARKER_RADIUS, size, 1, MARKER_COLOR);
+    graphics_window::fill_rect(left, top, 1, size, MARKER_COLOR);
+    graphics_window::fill_rect(x + MARKER_RADIUS, top, 1, size, MARKER_COLOR);
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,228 @@
+//! Building a world from an image, so that mazes and arenas can be painted in any image editor.
+//!
+//! The world is the size of the image, and each pixel becomes whichever of these it is closest in colour to:
+//! - white: empty space
+//! - black: a wall
+//! - red: a Wanderer
+//! - green: a Cruiser
+//! - blue: an Idler
+//!
+//! Transparent pixels are empty space. Entities face in random directions, and their lineage is chosen
+//! so that the lineage colour mode draws them in the colour they were painted.
+//! Binary (P6) and plain (P3) PPM images and PNG images of any colour type are supported.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use crate::behaviour::Behaviour;
+use crate::entity::Entity;
+use crate::topology::Topology;
+use crate::world::{Location, Terrain, World};
+
+/// What a pixel of the layout image becomes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Cell {
+    Empty,
+    Wall,
+    /// An entity with the given behaviour and lineage.
+    Entity(Behaviour, u32),
+}
+
+const PALETTE: [([u8; 3], Cell); 5] = [
+    ([255, 255, 255], Cell::Empty),
+    ([0, 0, 0], Cell::Wall),
+    ([255, 0, 0], Cell::Entity(Behaviour::Wanderer, 0)),
+    ([0, 255, 0], Cell::Entity(Behaviour::Cruiser, 3)),
+    ([0, 0, 255], Cell::Entity(Behaviour::Idler, 5)),
+];
+
+/// An image decoded to 8-bit RGBA, in rows from the top.
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+/// Whether the path names an image that load can read, going by its extension.
+pub fn is_layout_path(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.ends_with(".ppm") || path.ends_with(".png")
+}
+
+/// Loads the image as a world with the given topology, one cell per pixel.
+pub fn load(path: &str, topology: Topology) -> Result<World, String> {
+    let image = read_image(path).map_err(|error| format!("{}: {}", path, error))?;
+    build(&image, topology).map_err(|error| format!("{}: {}", path, error))
+}
+
+fn read_image(path: &str) -> Result<Image, String> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|file| BufReader::new(file).read_to_end(&mut bytes))
+        .map_err(|error| error.to_string())?;
+    match bytes.get(..2) {
+        Some(b"P6") | Some(b"P3") => read_ppm(&bytes),
+        Some([0x89, b'P']) => read_png(&bytes),
+        _ => Err(String::from("not a PPM or PNG image")),
+    }
+}
+
+fn build(image: &Image, topology: Topology) -> Result<World, String> {
+    if image.width == 0 || image.height == 0 {
+        return Err(String::from("image is empty"));
+    }
+    let mut world = World::new(image.width, image.height);
+    world.set_topology(topology)?;
+    for (index, pixel) in image.pixels.iter().enumerate() {
+        let (x, y) = (index % image.width, index / image.width);
+        match cell_of(pixel) {
+            Cell::Empty => {}
+            Cell::Wall => world.set_terrain(&Location::at(x, y, &world), Terrain::Wall)?,
+            Cell::Entity(behaviour, lineage) => {
+                let mut entity = Entity::new(x, y, &world);
+                entity.behaviour = behaviour;
+                entity.lineage = lineage;
+                world.place_entity(entity)?;
+            }
+        }
+    }
+    Ok(world)
+}
+
+fn cell_of(pixel: &[u8; 4]) -> Cell {
+    if pixel[3] < 128 { return Cell::Empty }
+    let distance = |color: &[u8; 3]| -> u32 {
+        (0..3).map(|i| (pixel[i].abs_diff(color[i]) as u32).pow(2)).sum()
+    };
+    PALETTE.iter()
+        .min_by_key(|(color, _)| distance(color))
+        .map(|(_, cell)| *cell)
+        .unwrap()
+}
+
+fn read_ppm(bytes: &[u8]) -> Result<Image, String> {
+    let binary = &bytes[..2] == b"P6";
+    let mut position = 2;
+    let mut header = [0; 3];
+    for value in header.iter_mut() {
+        *value = read_ppm_number(bytes, &mut position)?;
+    }
+    let [width, height, max_value] = header;
+    if max_value == 0 || max_value > 65535 {
+        return Err(format!("invalid maximum value {}", max_value));
+    }
+    // A single whitespace character separates the header from binary data.
+    position += 1;
+
+    let sample_count = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(3))
+        .ok_or_else(|| format!("image size {}x{} is too large", width, height))?;
+    let samples: Vec<usize> = if binary {
+        let sample_size = if max_value < 256 { 1 } else { 2 };
+        let end = sample_count.checked_mul(sample_size).and_then(|length| position.checked_add(length));
+        let data = end.and_then(|end| bytes.get(position..end)).ok_or("image data is truncated")?;
+        data.chunks(sample_size)
+            .map(|sample| sample.iter().fold(0, |value, byte| (value << 8) | *byte as usize))
+            .collect()
+    } else {
+        (0..sample_count).map(|_| read_ppm_number(bytes, &mut position)).collect::<Result<_, _>>()?
+    };
+
+    let scale = |sample: usize| (sample.min(max_value) * 255 / max_value) as u8;
+    let pixels = samples.chunks(3)
+        .map(|rgb| [scale(rgb[0]), scale(rgb[1]), scale(rgb[2]), 255])
+        .collect();
+    Ok(Image { width, height, pixels })
+}
+
+/// Reads a decimal number from a PPM header or plain PPM data, skipping whitespace and comments before it.
+fn read_ppm_number(bytes: &[u8], position: &mut usize) -> Result<usize, String> {
+    loop {
+        match bytes.get(*position) {
+            Some(b'#') => while bytes.get(*position).is_some_and(|byte| *byte != b'\n') { *position += 1 },
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            _ => break,
+        }
+    }
+    let start = *position;
+    while bytes.get(*position).is_some_and(|byte| byte.is_ascii_digit()) {
+        *position += 1;
+    }
+    std::str::from_utf8(&bytes[start..*position]).unwrap()
+        .parse()
+        .map_err(|_| format!("expected a number at byte {}", start))
+}
+
+fn read_png(bytes: &[u8]) -> Result<Image, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|error| error.to_string())?;
+
+    let data = &buffer[..info.buffer_size()];
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => data.chunks(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+        png::ColorType::Rgb => data.chunks(3).map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => data.chunks(2).map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => data.iter().map(|p| [*p, *p, *p, 255]).collect(),
+        png::ColorType::Indexed => return Err(String::from("indexed colour was not expanded")),
+    };
+    Ok(Image { width: info.width as usize, height: info.height as usize, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng_buffer;
+
+    /// What each cell of the world is, as the palette cell it would be painted with, in rows from the top.
+    fn cells_of(world: &World) -> Vec<Cell> {
+        (0..world.height()).flat_map(|y| (0..world.width()).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let location = Location::at(x, y, world);
+                match world.get_entity_id(&location).and_then(|id| world.entity(id)) {
+                    Some(entity) => Cell::Entity(entity.behaviour(), entity.lineage()),
+                    None if world.terrain_at(&location) == Terrain::Wall => Cell::Wall,
+                    None => Cell::Empty,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plain_ppm_pixels_become_walls_and_entities() {
+        let _lock = rng_buffer::lock_for_test();
+        // Off-palette colours go to the closest one, and values are scaled from the maximum value.
+        let image = read_ppm(b"P3\n# a comment\n3 2 15\n15 15 15  1 0 2  14 1 0\n0 15 0  0 0 15  12 12 12\n").unwrap();
+        let world = build(&image, Topology::Moore).unwrap();
+        assert_eq!((world.width(), world.height()), (3, 2));
+        assert_eq!(cells_of(&world), PALETTE.map(|(_, cell)| cell).into_iter().chain([Cell::Empty]).collect::<Vec<_>>());
+        assert_eq!(world.entity_count(), 3);
+    }
+
+    #[test]
+    fn png_pixels_become_walls_and_entities() {
+        let _lock = rng_buffer::lock_for_test();
+        let pixels: [[u8; 4]; 6] = [
+            [0, 0, 0, 255], [255, 0, 0, 255], [0, 0, 0, 0],
+            [0, 0, 255, 255], [255, 255, 255, 255], [0, 255, 0, 200],
+        ];
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 3, 2);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.write_header().unwrap().write_image_data(pixels.as_flattened()).unwrap();
+
+        let world = build(&read_png(&bytes).unwrap(), Topology::Moore).unwrap();
+        assert_eq!(cells_of(&world), [
+            Cell::Wall, Cell::Entity(Behaviour::Wanderer, 0), Cell::Empty,
+            Cell::Entity(Behaviour::Idler, 5), Cell::Empty, Cell::Entity(Behaviour::Cruiser, 3),
+        ]);
+    }
+
+    #[test]
+    fn oversized_ppm_header_is_an_error() {
+        let error = read_ppm(b"P6\n4000000000 4000000000 255\n\0\0\0").err().unwrap();
+        assert!(error.contains("too large"), "{}", error);
+        let error = read_ppm(b"P6\n2 2 255\n\0\0\0").err().unwrap();
+        assert!(error.contains("truncated"), "{}", error);
+    }
+}
@@ -0,0 +1,56 @@
+/// Creates a public getter function for given (Copy) field.
+#[allow(unused)]
+macro_rules! getter {
+    ($var:ident: $t:ty) => {
+        pub fn $var(&self) -> $t { self.$var }
+    };
+}
+
+/// Creates a public reference-getter function for given field.
+#[allow(unused)]
+macro_rules! getter_mut {
+    ($var:ident: $typ:ty) => {
+        pub fn $var(&mut self) -> &mut $typ { &mut self.$var }
+    };
+}
+
+/// Creates a public reference-getter function for given field.
+#[allow(unused)]
+macro_rules! getter_ref {
+    ($var:ident: $typ:ty) => {
+        pub fn $var(&self) -> &$typ { &self.$var }
+    };
+}
+
+pub mod graphics_window;
+pub mod rng_buffer;
+pub mod matrix_test;
+pub mod world;
+pub mod fps_counter;
+pub mod world_processor;
+pub mod entity;
+pub mod action;
+pub mod bitmap_font;
+pub mod hud;
+pub mod behaviour;
+pub mod color_mode;
+pub mod heatmap;
+pub mod timestep;
+pub mod profiler;
+pub mod test_world;
+pub mod stats;
+pub mod binary_io;
+pub mod snapshot;
+pub mod event_log;
+pub mod history;
+pub mod scenario;
+pub mod layout;
+pub mod execution;
+pub mod worker;
+pub mod chunked_grid;
+pub mod entity_storage;
+pub mod spatial;
+pub mod clusters;
+pub mod pathfinding;
+pub mod topology;
+pub mod update_scheme;
@@ -1,45 +1,53 @@
-/// Creates a public getter function for given (Copy) field.
-#[allow(unused)]
-macro_rules! getter {
-    ($var:ident: $t:ty) => {
-        pub fn $var(&self) -> $t { self.$var }
-    };
-}
-
-/// Creates a public reference-getter function for given field.
-#[allow(unused)]
-macro_rules! getter_mut {
-    ($var:ident: $typ:ty) => {
-        pub fn $var(&mut self) -> &mut $typ { &mut self.$var }
-    };
-}
-
-/// Creates a public reference-getter function for given field.
-#[allow(unused)]
-macro_rules! getter_ref {
-    ($var:ident: $typ:ty) => {
-        pub fn $var(&self) -> &$typ { &self.$var }
-    };
-}
+use std::time::Instant;
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, StartCause, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow};
+use graphics_test::{graphics_window, layout, rng_buffer, scenario, snapshot, test_world, world_processor};
+use graphics_test::color_mode::ColorMode;
+use graphics_test::entity_storage::EntityLayout;
+use graphics_test::topology::Topology;
+use graphics_test::update_scheme::UpdateScheme;
+use graphics_test::execution::Execution;
+use graphics_test::fps_counter::{FpsCounter, Mode};
+use graphics_test::graphics_window::WindowConfig;
+use graphics_test::hud::{Hud, HudInfo};
+use graphics_test::profiler::Phase;
+use graphics_test::timestep::Timestep;
+use graphics_test::world::World;
 
-mod graphics_window;
-mod rng_buffer;
-mod matrix_test;
-mod world;
-mod fps_counter;
-mod world_processor;
-mod entity;
-mod action;
+const USAGE: &str = "\
+Usage: graphics_test [options]
 
-use std::ops::Add;
-use std::time::{Duration, Instant};
-use winit::event::{Event, StartCause, WindowEvent};
-use winit::event_loop::{ControlFlow};
-use winit::window::WindowId;
-use crate::fps_counter::FpsCounter;
-use crate::graphics_window::WindowConfig;
-use crate::world::World;
+Options:
+    --size <WxH>              world and window size for the test world (default 1800x900)
+    --entities <n>            entities in the test world (default 50000)
+    --scenario <path>         start from a scenario file, or a PPM or PNG layout image
+    --load <path>             start from a snapshot
+    --seed <n>                RNG seed (default: the scenario's seed, or random)
+    --title <text>            window title (default Test)
+    --resizable               let the window be resized
+    --tps <n>                 target ticks per second (default 1000)
+    --fps <n>                 target frames per second (default 60)
+    --fps-counter <mode>      print tick and frame rates every 4, 8, 16 or 32 frames, or off (default 32)
+    --headless                run without a window, as fast as possible
+    --ticks <n>               exit once the world reaches this tick
+    --record <dir>            record events to this directory from the start
+    --stats                   write stats, including clusters, to stats.csv every 10 ticks from the start
+    --threads <n>             worker threads (default: number of CPUs)
+    --sequential              run everything on one thread, for debugging and determinism checks
+    --layout <aos|soa>        store entities as an array of structs or a struct of arrays (default aos)
+    --topology <name>         moore, von-neumann or hex grid for the test world, a layout image or a scenario
+                              (default moore, or the scenario's)
+    --update <scheme>         synchronous, random-sequential or checkerboard order of acting (default synchronous)
+    --heatmap-decay <f>       what the heatmap is multiplied by every tick, from 0 to 1 (default 0.99)
+    --help                    show this message";
 
+const PROFILE_CSV_PATH: &str = "profile.csv";
+const PROFILE_TRACE_PATH: &str = "profile_trace.json";
+const STATS_PATH: &str = "stats.csv";
+const STATS_INTERVAL: u64 = 10;
+const RECORDING_DIR: &str = "recording";
+/// How many past ticks can be stepped back through.
+const HISTORY_TICKS: usize = 600;
 
 fn main() {
 
@@ -57,50 +65,356 @@ fn main() {
 
     /**********************************************************************************************/
 
-    let (width, height) = (1800, 900);
+    let config = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let execution = match config.threads {
+        _ if config.sequential => Some(Execution::Sequential),
+        Some(threads) => Some(Execution::Parallel(threads)),
+        None => None,
+    };
+    if let Some(execution) = execution {
+        if let Err(error) = world_processor::set_execution(execution) {
+            eprintln!("failed to set up threads: {}", error);
+            std::process::exit(1);
+        }
+    }
+
+    let (mut world, tick) = match load_world(&config) {
+        Ok(result) => result,
+        Err(error) => {
+            eprintln!("failed to load world: {}", error);
+            std::process::exit(1);
+        }
+    };
+    world.set_entity_layout(config.layout);
+    let (width, height) = world.topology().pixel_size(world.width(), world.height());
+    world_processor::init_at_tick(world, tick).unwrap();
+    world_processor::set_update_scheme(config.update_scheme);
+    if let Some(decay) = config.heatmap_decay {
+        world_processor::set_heatmap_decay(decay);
+    }
+
+    if let Some(dir) = &config.record {
+        match world_processor::start_recording(dir) {
+            Ok(()) => println!("recording to {} from tick {}", dir, tick),
+            Err(error) => {
+                eprintln!("failed to start recording to {}: {}", dir, error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if config.stats {
+        if let Err(error) = world_processor::enable_stats(STATS_PATH, STATS_INTERVAL) {
+            eprintln!("failed to write stats to {}: {}", STATS_PATH, error);
+            std::process::exit(1);
+        }
+    }
+
+    let mut tps_counter = FpsCounter::with_mode(config.counter_mode()).named("tps");
+    if config.headless {
+        run_headless(config.ticks, &mut tps_counter);
+        return;
+    }
+
     let window_config: WindowConfig = WindowConfig {
-        title: String::from("Test"),
-        resizable: false,
+        title: config.title.clone(),
+        resizable: config.resizable,
         width: width as u32,
         height: height as u32,
     };
-    let target_fps = 1000;
-    let target_frame_time: Duration = Duration::from_millis(1000 / target_fps);
-    let mut next_tick = Instant::now().add(target_frame_time);
 
-    let mut fps_counter = FpsCounter::every_32_frames();
+    let mut fps_counter = FpsCounter::with_mode(config.counter_mode());
+    let mut hud = Hud::new(false);
+    let mut paused = false;
+    let mut cursor_position = (0, 0);
+    let mut inspected = None;
 
-    rng_buffer::init();
-
-    let mut world = World::new(width, height);
-    load_test_world(&mut world, 50_000);
-    world_processor::init(world).unwrap();
+    world_processor::enable_history(HISTORY_TICKS);
 
     let (mut graphics_window, event_loop) = graphics_window::build_graphics_window(window_config);
 
-    event_loop.run(move |event, _, control_flow| {
+    let mut timestep = Timestep::new(config.target_tps, config.target_fps);
 
-        if Instant::now() > next_tick {
-            next_tick = next_tick.add(target_frame_time);
-            *control_flow = ControlFlow::WaitUntil(next_tick);
-        }
+    event_loop.run(move |event, _, control_flow| {
 
         match handle_event(&event) {
             EventResponse::Exit => { *control_flow = ControlFlow::Exit }
-            EventResponse::RedrawRequested(_) => graphics_window.redraw(),
+            EventResponse::RedrawRequested => {
+                hud.draw(&HudInfo {
+                    fps: fps_counter.stats(),
+                    tps: tps_counter.stats(),
+                    tick: world_processor::tick(),
+                    entity_count: world_processor::entity_count(),
+                    phase_means: Phase::ALL.iter()
+                        .map(|phase| (*phase, world_processor::phase_stats(*phase).mean))
+                        .collect(),
+                    paused,
+                    topology: world_processor::topology(),
+                    update_scheme: world_processor::update_scheme(),
+                    color_mode: world_processor::color_mode(),
+                    heatmap_kind: world_processor::heatmap_kind(),
+                    heatmap_overlay: world_processor::heatmap_overlay(),
+                    history: world_processor::history_range(),
+                    inspected: inspected.and_then(|id| world_processor::inspect_entity(id).map(|entity| (id, entity))),
+                });
+                graphics_window.redraw();
+            }
             EventResponse::Tick => {
-                fps_counter.tick();
-                world_processor::step();
-                graphics_window.window().request_redraw();
+                let now = Instant::now();
+                let ticks = timestep.take_due_ticks(now);
+                let frame_due = timestep.take_due_frame(now);
+
+                let ticks = if paused { 0 } else { ticks_before_end(config.ticks, ticks) };
+                for i in 1..=ticks {
+                    tps_counter.tick();
+                    // Drawing overlaps with the start of the step, so it's done on the last tick before a frame.
+                    world_processor::step(frame_due && i == ticks);
+                }
+
+                if frame_due {
+                    if ticks == 0 {
+                        world_processor::render();
+                    }
+                    fps_counter.tick();
+                    graphics_window.window().request_redraw();
+                }
+                if config.ticks.is_some_and(|end| world_processor::tick() >= end) {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            EventResponse::TogglePause => paused = !paused,
+            EventResponse::ToggleHud => hud.toggle(),
+            EventResponse::NextColorMode => world_processor::set_color_mode(world_processor::color_mode().next()),
+            EventResponse::NextUpdateScheme => world_processor::set_update_scheme(world_processor::update_scheme().next()),
+            EventResponse::NextHeatmapKind => world_processor::set_heatmap_kind(world_processor::heatmap_kind().next()),
+            EventResponse::NextHeatmapOverlay => world_processor::set_heatmap_overlay(world_processor::heatmap_overlay().next()),
+            EventResponse::ResetHeatmap => world_processor::reset_heatmap(),
+            EventResponse::ExportProfile => match world_processor::export_profile(PROFILE_CSV_PATH, PROFILE_TRACE_PATH) {
+                Ok(()) => println!("wrote profile to {} and {}", PROFILE_CSV_PATH, PROFILE_TRACE_PATH),
+                Err(error) => println!("failed to write profile: {}", error),
+            },
+            EventResponse::ToggleStats => if world_processor::is_stats_enabled() {
+                world_processor::disable_stats();
+                println!("stopped writing stats to {}", STATS_PATH);
+            } else {
+                match world_processor::enable_stats(STATS_PATH, STATS_INTERVAL) {
+                    Ok(()) => println!("writing stats to {} every {} ticks", STATS_PATH, STATS_INTERVAL),
+                    Err(error) => println!("failed to write stats: {}", error),
+                }
+            },
+            EventResponse::ToggleRecording => if world_processor::is_recording() {
+                world_processor::stop_recording();
+                println!("stopped recording to {}", RECORDING_DIR);
+            } else {
+                match world_processor::start_recording(RECORDING_DIR) {
+                    Ok(()) => println!("recording to {} from tick {}", RECORDING_DIR, world_processor::tick()),
+                    Err(error) => println!("failed to start recording: {}", error),
+                }
+            },
+            EventResponse::StepBack => {
+                paused = true;
+                let tick = world_processor::tick();
+                if let Err(error) = world_processor::rewind_to(tick.saturating_sub(1)) {
+                    println!("can't step back: {}", error);
+                }
+            }
+            EventResponse::StepForward => {
+                paused = true;
+                let tick = world_processor::tick();
+                match world_processor::history_range() {
+                    Some((_, latest)) if tick < latest => {
+                        if let Err(error) = world_processor::rewind_to(tick + 1) {
+                            println!("can't step forward: {}", error);
+                        }
+                    }
+                    _ => world_processor::step(false),
+                }
+            }
+            EventResponse::CursorMoved(x, y) => cursor_position = (x, y),
+            EventResponse::Inspect => {
+                inspected = world_processor::entity_id_at(cursor_position.0, cursor_position.1);
             }
             EventResponse::None => {}
         }
+
+        if *control_flow != ControlFlow::Exit {
+            *control_flow = ControlFlow::WaitUntil(timestep.next_wake());
+        }
     });
 }
 
+/// Runs the given number of ticks (or forever) as fast as possible, without a window.
+fn run_headless(ticks: Option<u64>, tps_counter: &mut FpsCounter) {
+    world_processor::set_drawing_enabled(false);
+    let start = Instant::now();
+    let start_tick = world_processor::tick();
+    while ticks.is_none_or(|end| world_processor::tick() < end) {
+        tps_counter.tick();
+        world_processor::step(false);
+    }
+    let ran = world_processor::tick() - start_tick;
+    println!("ran {} ticks in {:.3} s", ran, start.elapsed().as_secs_f64());
+}
+
+/// How many of the due ticks can run without passing the tick to end at.
+fn ticks_before_end(end: Option<u64>, due_ticks: u32) -> u32 {
+    match end {
+        None => due_ticks,
+        Some(end) => end.saturating_sub(world_processor::tick()).min(due_ticks as u64) as u32,
+    }
+}
+
+/// The world to start from, and the tick it is at.
+fn load_world(config: &Config) -> Result<(World, u64), String> {
+    if let Some(path) = &config.load {
+        seed_rng(config.seed);
+        return snapshot::load(path);
+    }
+    let Some(path) = &config.scenario else {
+        seed_rng(config.seed);
+        let (width, height) = config.size;
+        let mut world = World::new(width, height);
+        let topology = config.topology.unwrap_or(Topology::Moore);
+        world.set_topology(topology)?;
+        test_world::load(&mut world, config.entities.min(u32::try_from(width * height).unwrap_or(u32::MAX)));
+        return Ok((world, 0));
+    };
+
+    world_processor::set_color_mode(ColorMode::Lineage);
+    if layout::is_layout_path(path) {
+        seed_rng(config.seed);
+        return Ok((layout::load(path, config.topology.unwrap_or(Topology::Moore))?, 0));
+    }
+    let mut scenario = scenario::load(path)?;
+    if let Some(topology) = config.topology {
+        scenario.topology = topology;
+    }
+    seed_rng(config.seed.or(scenario.seed));
+    let world = scenario.build().map_err(|error| format!("{}: {}", path, error))?;
+    Ok((world, 0))
+}
+
+fn seed_rng(seed: Option<u64>) {
+    match seed {
+        Some(seed) => rng_buffer::init_with_seed(seed),
+        None => rng_buffer::init(),
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Config, String> {
+    let mut config = Config {
+        size: (1800, 900),
+        title: String::from("Test"),
+        resizable: false,
+        target_tps: 1000,
+        target_fps: 60,
+        entities: 50_000,
+        counter_interval: 32,
+        headless: false,
+        ticks: None,
+        seed: None,
+        scenario: None,
+        load: None,
+        record: None,
+        stats: false,
+        threads: None,
+        sequential: false,
+        layout: EntityLayout::Aos,
+        topology: None,
+        update_scheme: UpdateScheme::Synchronous,
+        heatmap_decay: None,
+    };
+
+    let mut args = args.into_iter();
+    while let Some(flag) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", flag));
+        match flag.as_str() {
+            "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--resizable" => config.resizable = true,
+            "--headless" => config.headless = true,
+            "--sequential" => config.sequential = true,
+            "--stats" => config.stats = true,
+            "--size" => config.size = parse_size(&value()?)?,
+            "--title" => config.title = value()?,
+            "--tps" => config.target_tps = parse_number(&value()?)?,
+            "--fps" => config.target_fps = parse_number(&value()?)?,
+            "--entities" => config.entities = parse_number(&value()?)?,
+            "--fps-counter" => config.counter_interval = match value()?.as_str() {
+                "off" => 0,
+                interval @ ("4" | "8" | "16" | "32") => parse_number(interval)?,
+                other => return Err(format!("expected off, 4, 8, 16 or 32 but found '{}'", other)),
+            },
+            "--ticks" => config.ticks = Some(parse_number(&value()?)?),
+            "--seed" => config.seed = Some(parse_number(&value()?)?),
+            "--scenario" => config.scenario = Some(value()?),
+            "--load" => config.load = Some(value()?),
+            "--record" => config.record = Some(value()?),
+            "--threads" => config.threads = Some(parse_number(&value()?)?),
+            "--layout" => config.layout = parse_layout(&value()?)?,
+            "--topology" => config.topology = Some(parse_topology(&value()?)?),
+            "--update" => config.update_scheme = parse_update_scheme(&value()?)?,
+            "--heatmap-decay" => config.heatmap_decay = Some(parse_number(&value()?)?),
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+    }
+
+    if config.scenario.is_some() && config.load.is_some() {
+        return Err(String::from("--scenario and --load can't be used together"));
+    }
+    if config.load.is_some() && config.topology.is_some() {
+        return Err(String::from("--topology and --load can't be used together"));
+    }
+    if config.sequential && config.threads.is_some() {
+        return Err(String::from("--threads and --sequential can't be used together"));
+    }
+    if config.target_tps == 0 || config.target_fps == 0 {
+        return Err(String::from("--tps and --fps must be above zero"));
+    }
+    if config.heatmap_decay.is_some_and(|decay| !(0.0..=1.0).contains(&decay)) {
+        return Err(String::from("--heatmap-decay must be from 0 to 1"));
+    }
+    Ok(config)
+}
+
+fn parse_layout(value: &str) -> Result<EntityLayout, String> {
+    EntityLayout::from_name(value).ok_or(format!("expected aos or soa but found '{}'", value))
+}
+
+fn parse_topology(value: &str) -> Result<Topology, String> {
+    Topology::from_name(value).ok_or(format!("expected moore, von-neumann or hex but found '{}'", value))
+}
+
+fn parse_update_scheme(value: &str) -> Result<UpdateScheme, String> {
+    UpdateScheme::from_name(value)
+        .ok_or(format!("expected synchronous, random-sequential or checkerboard but found '{}'", value))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("expected a number but found '{}'", value))
+}
+
+fn parse_size(value: &str) -> Result<(usize, usize), String> {
+    let (width, height) = value.split_once('x').ok_or(format!("expected WxH but found '{}'", value))?;
+    let size = (parse_number(width)?, parse_number(height)?);
+    if size.0 == 0 || size.1 == 0 {
+        return Err(format!("size must not be empty but found '{}'", value));
+    }
+    Ok(size)
+}
+
 fn handle_event(event: &Event<()>) -> EventResponse {
     match event {
-        Event::RedrawRequested(window_id) => EventResponse::RedrawRequested(*window_id),
+        Event::RedrawRequested(_) => EventResponse::RedrawRequested,
         Event::WindowEvent { event: window_event, .. } => handle_window_event(window_event),
         Event::NewEvents(StartCause::ResumeTimeReached { .. }) => EventResponse::Tick,
         _ => EventResponse::None,
@@ -109,24 +423,100 @@ fn handle_event(event: &Event<()>) -> EventResponse {
 
 fn handle_window_event(event: &WindowEvent) -> EventResponse {
     match event {
-        WindowEvent::CloseRequested => { return EventResponse::Exit }
-        WindowEvent::KeyboardInput { .. } => EventResponse::None,
+        WindowEvent::CloseRequested => EventResponse::Exit,
+        WindowEvent::KeyboardInput { input, .. } => handle_keyboard_input(input),
+        WindowEvent::CursorMoved { position, .. } => EventResponse::CursorMoved(position.x as usize, position.y as usize),
+        WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } => EventResponse::Inspect,
+        _ => EventResponse::None,
+    }
+}
+
+fn handle_keyboard_input(input: &KeyboardInput) -> EventResponse {
+    if input.state != ElementState::Pressed { return EventResponse::None }
+    match input.virtual_keycode {
+        Some(VirtualKeyCode::Space) => EventResponse::TogglePause,
+        Some(VirtualKeyCode::H) => EventResponse::ToggleHud,
+        Some(VirtualKeyCode::C) => EventResponse::NextColorMode,
+        Some(VirtualKeyCode::U) => EventResponse::NextUpdateScheme,
+        Some(VirtualKeyCode::M) => EventResponse::NextHeatmapKind,
+        Some(VirtualKeyCode::O) => EventResponse::NextHeatmapOverlay,
+        Some(VirtualKeyCode::R) => EventResponse::ResetHeatmap,
+        Some(VirtualKeyCode::P) => EventResponse::ExportProfile,
+        Some(VirtualKeyCode::S) => EventResponse::ToggleStats,
+        Some(VirtualKeyCode::L) => EventResponse::ToggleRecording,
+        Some(VirtualKeyCode::Left) => EventResponse::StepBack,
+        Some(VirtualKeyCode::Right) => EventResponse::StepForward,
         _ => EventResponse::None,
     }
 }
 
+struct Config {
+    size: (usize, usize),
+    title: String,
+    resizable: bool,
+    target_tps: u32,
+    target_fps: u32,
+    entities: u32,
+    /// How many frames between printing tick and frame rates, or 0 to not print them.
+    counter_interval: u32,
+    headless: bool,
+    ticks: Option<u64>,
+    seed: Option<u64>,
+    scenario: Option<String>,
+    load: Option<String>,
+    record: Option<String>,
+    stats: bool,
+    threads: Option<usize>,
+    sequential: bool,
+    layout: EntityLayout,
+    /// The topology to use in place of the default or the scenario's.
+    topology: Option<Topology>,
+    update_scheme: UpdateScheme,
+    /// The heatmap decay to use in place of the default.
+    heatmap_decay: Option<f32>,
+}
+
+impl Config {
+    fn counter_mode(&self) -> Mode {
+        match self.counter_interval {
+            4 => Mode::Every4Frames,
+            8 => Mode::Every8Frames,
+            16 => Mode::Every16Frames,
+            32 => Mode::Every32Frames,
+            _ => Mode::Off,
+        }
+    }
+}
+
 enum EventResponse {
-    None, Exit, RedrawRequested(WindowId), Tick
+    None, Exit, RedrawRequested, Tick, TogglePause, ToggleHud, NextColorMode, NextUpdateScheme,
+    NextHeatmapKind, NextHeatmapOverlay, ResetHeatmap, ExportProfile, ToggleStats,
+    ToggleRecording, StepBack, StepForward, CursorMoved(usize, usize), Inspect,
 }
 
-fn load_test_world(world: &mut World, entity_count: u32) {
-    let mut count = 0;
-    while count < entity_count {
-        let x = (rng_buffer::generate_next() * world.width() as f64) as usize;
-        let y = (rng_buffer::next() * world.height() as f64) as usize;
-        let entity = entity::Entity::new(x, y, &world);
-        if world.place_entity(entity).is_ok() {
-            count += 1;
+#[cfg(test)]
+mod tests {
+    use graphics_test::action::Action;
+    use graphics_test::entity::Entity;
+    use super::*;
+
+    #[test]
+    fn loaded_snapshot_still_moves() {
+        // Built before anything seeds rng_buffer, as in a process that only loads a snapshot.
+        let mut world = World::new(40, 30);
+        for i in 0..100 {
+            world.place_entity(Entity::new(i % 40, i / 40 * 10, &world)).unwrap();
         }
+        let path = std::env::temp_dir().join("graphics_test_loaded_snapshot_still_moves.snapshot");
+        let path = path.to_str().unwrap();
+        snapshot::save(path, &world, 0).unwrap();
+
+        let config = parse_args(vec![String::from("--load"), String::from(path)]).unwrap();
+        let (world, _) = load_world(&config).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let moves = (0..world.entity_count())
+            .filter(|id| matches!(world.entity(*id).unwrap().determine_action(&world), Action::Move(_)))
+            .count();
+        assert!(moves > world.entity_count() / 2, "only {} of {} entities moved", moves, world.entity_count());
     }
-}
\ No newline at end of file
+}
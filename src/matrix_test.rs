@@ -73,10 +73,11 @@ impl NeuralNet {
         let bias = &self.bias;
 
         let mut output = connections * input;
-        output = output + bias;
+        output += bias;
+        assert_eq!(output.len(), self.output_layer_size);
 
         // TODO - normalise.
 
-        return DVector::from_data(output.data);
+        DVector::from_data(output.data)
     }
 }
\ No newline at end of file
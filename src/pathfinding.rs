@@ -0,0 +1,419 @@
+//! Shortest paths over the world's grid for entities heading somewhere: A* for one entity going to one place,
+//! and flow fields for many entities heading to the same places, which each read their next step from the field.
+//! Paths go around walls and across wrapped edges, but not around entities, which will have moved by the time
+//! the path is followed. No Behaviour follows them yet, so for now they are only used from tests.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use crate::chunked_grid::ChunkedGrid;
+use crate::topology::Topology;
+use crate::world::{Direction, Location, Terrain, World};
+
+/// The cost of a step onto open ground. Diagonal steps in Moore worlds cost about √2 times as much.
+pub const STRAIGHT_COST: u32 = 10;
+pub const DIAGONAL_COST: u32 = 14;
+
+/// The cost of a step in the given direction onto the given terrain, or None if it can't be moved onto.
+pub fn step_cost(topology: Topology, terrain: Terrain, direction: &Direction) -> Option<u32> {
+    let cost = if topology.is_diagonal(direction) { DIAGONAL_COST } else { STRAIGHT_COST };
+    terrain.move_cost().map(|multiplier| multiplier * cost)
+}
+
+/// The cheapest possible cost between two locations, which is what it costs if all the ground between is open.
+fn estimate(world: &World, from: &Location, to: &Location) -> u32 {
+    let (dx, dy) = world.displacement(from, to);
+    let (dx, dy) = (dx.unsigned_abs() as u32, dy.unsigned_abs() as u32);
+    match world.topology() {
+        Topology::Moore => DIAGONAL_COST * dx.min(dy) + STRAIGHT_COST * (dx.max(dy) - dx.min(dy)),
+        Topology::VonNeumann => STRAIGHT_COST * (dx + dy),
+        // Each step to another row also moves half a column, so the rows can cover up to dy / 2 columns for free.
+        Topology::Hex => STRAIGHT_COST * (dy + dx.saturating_sub(dy.div_ceil(2))),
+    }
+}
+
+/// For each cell reached by A*: the cheapest cost to it so far, and the cell and step it was reached by.
+type Reached = HashMap<usize, (u32, Option<(Location, Direction)>)>;
+
+pub struct Path {
+    pub steps: Vec<Direction>,
+    pub cost: u32,
+}
+
+/// The cheapest path between two locations, or None if there is none costing at most max_cost.
+/// The search covers every cell cheaper to reach than the destination, so max_cost keeps it bounded
+/// where the destination is far away or walled off.
+pub fn find_path(world: &World, from: &Location, to: &Location, max_cost: u32) -> Option<Path> {
+    let mut reached = Reached::new();
+    let mut open = BinaryHeap::new();
+    reached.insert(from.index(), (0, None));
+    open.push(Reverse((estimate(world, from, to), 0, from.index())));
+
+    while let Some(Reverse((_, cost, index))) = open.pop() {
+        let location = location_of(world, index);
+        if location.index() == to.index() {
+            return Some(Path { steps: steps_to(&reached, to), cost });
+        }
+        if cost > reached[&location.index()].0 { continue }
+        for &direction in world.directions() {
+            let Some(next) = world.neighbour(&location, &direction) else { continue };
+            let Some(step) = step_cost(world.topology(), world.terrain_at(&next), &direction) else { continue };
+            let next_cost = cost + step;
+            if next_cost > max_cost || reached.get(&next.index()).is_some_and(|(best, _)| *best <= next_cost) {
+                continue;
+            }
+            reached.insert(next.index(), (next_cost, Some((location, direction))));
+            open.push(Reverse((next_cost + estimate(world, &next, to), next_cost, next.index())));
+        }
+    }
+    None
+}
+
+fn steps_to(reached: &Reached, to: &Location) -> Vec<Direction> {
+    let mut steps = Vec::new();
+    let mut location = *to;
+    while let (_, Some((previous, direction))) = reached[&location.index()] {
+        steps.push(direction);
+        location = previous;
+    }
+    steps.reverse();
+    steps
+}
+
+/// The location with the given index, for queues that hold indices so that they can be ordered.
+fn location_of(world: &World, index: usize) -> Location {
+    Location::at(index % world.width(), index / world.width(), world)
+}
+
+/// A cell's cost to the nearest target.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Distance(u32);
+
+impl Distance {
+    const UNREACHED: Distance = Distance(u32::MAX);
+}
+
+impl Default for Distance {
+    fn default() -> Distance {
+        Distance::UNREACHED
+    }
+}
+
+/// The cost from each cell to the nearest of a set of targets, shared by every entity heading to them.
+/// Cells costing more than the range to a target are left unreached, so a field over a large world only
+/// takes memory near its targets. After the targets or the world's terrain change, the field is updated by
+/// recomputing only the cells whose costs changed.
+pub struct FlowField {
+    targets: Vec<Location>,
+    range: u32,
+    distances: ChunkedGrid<Distance>,
+}
+
+impl FlowField {
+    getter!(range: u32);
+
+    pub fn new(world: &World, targets: &[Location], range: u32) -> FlowField {
+        let mut field = FlowField {
+            targets: Vec::new(),
+            range,
+            distances: ChunkedGrid::new(world.width(), world.height()),
+        };
+        for target in targets {
+            field.add_target(world, target);
+        }
+        field
+    }
+
+    pub fn targets(&self) -> &[Location] {
+        &self.targets
+    }
+
+    /// The cost from the location to the nearest target, if it is within range.
+    pub fn distance(&self, location: &Location) -> Option<u32> {
+        let distance = *self.distances.get(location.x(), location.y());
+        (distance != Distance::UNREACHED).then_some(distance.0)
+    }
+
+    /// The step toward the nearest target, or None at a target or out of range of them.
+    /// Steps that cost the same are chosen between in the order of Direction::ALL.
+    pub fn direction(&self, world: &World, location: &Location) -> Option<Direction> {
+        if self.distance(location)? == 0 { return None }
+        world.directions().iter().copied()
+            .filter_map(|direction| {
+                let next = world.neighbour(location, &direction)?;
+                let cost = step_cost(world.topology(), world.terrain_at(&next), &direction)? + self.distance(&next)?;
+                Some((cost, direction))
+            })
+            .min_by_key(|(cost, direction)| (*cost, direction.index()))
+            .map(|(_, direction)| direction)
+    }
+
+    pub fn add_target(&mut self, world: &World, target: &Location) {
+        if self.targets.iter().any(|existing| existing.index() == target.index()) { return }
+        self.targets.push(*target);
+        if world.terrain_at(target).move_cost().is_none() { return }
+        let mut queue = BinaryHeap::new();
+        self.lower(target, 0, &mut queue);
+        self.propagate(world, queue);
+    }
+
+    pub fn remove_target(&mut self, world: &World, target: &Location) {
+        let Some(position) = self.targets.iter().position(|existing| existing.index() == target.index()) else { return };
+        self.targets.swap_remove(position);
+        self.raise(world, target);
+    }
+
+    /// Updates the field for a change to the terrain at the location, which should be called after every change.
+    pub fn update_terrain(&mut self, world: &World, location: &Location) {
+        if self.distance(location).is_some() {
+            self.raise(world, location);
+            return;
+        }
+        let mut queue = BinaryHeap::new();
+        self.reach_again(world, location, &mut queue);
+        self.propagate(world, queue);
+    }
+
+    /// Sets the cell's distance if it is lower, queueing it to pass the change on to its neighbours.
+    fn lower(&mut self, location: &Location, distance: u32, queue: &mut BinaryHeap<Reverse<(u32, usize)>>) {
+        if distance > self.range { return }
+        let cell = self.distances.get_mut(location.x(), location.y());
+        if distance < cell.0 {
+            *cell = Distance(distance);
+            queue.push(Reverse((distance, location.index())));
+        }
+    }
+
+    /// Lowers the distances of cells that can step onto the queued cells, and so on outward, in order of distance.
+    fn propagate(&mut self, world: &World, mut queue: BinaryHeap<Reverse<(u32, usize)>>) {
+        while let Some(Reverse((distance, index))) = queue.pop() {
+            let location = location_of(world, index);
+            if self.distance(&location) != Some(distance) { continue }
+            for &direction in world.directions() {
+                // The cell that steps onto this one in the direction.
+                let Some(from) = world.neighbour(&location, &direction.opposite()) else { continue };
+                if world.terrain_at(&from).move_cost().is_none() { continue }
+                let step = step_cost(world.topology(), world.terrain_at(&location), &direction).unwrap();
+                self.lower(&from, distance + step, &mut queue);
+            }
+        }
+    }
+
+    /// Unreaches the location and every cell whose distance may have been through it, then reaches them again
+    /// from the cells around them that are unaffected.
+    fn raise(&mut self, world: &World, location: &Location) {
+        let Some(distance) = self.distance(location) else { return };
+        *self.distances.get_mut(location.x(), location.y()) = Distance::UNREACHED;
+        let mut unreached = vec![*location];
+
+        // The cost of stepping onto the location may have changed, so any neighbour further away may have been
+        // through it. Further out, a cell was through a neighbour if it is exactly the step onto it further away.
+        let mut stack = Vec::new();
+        for &direction in world.directions() {
+            let Some(from) = world.neighbour(location, &direction.opposite()) else { continue };
+            if self.distance(&from).is_some_and(|from_distance| from_distance > distance) {
+                stack.push((from, self.distance(&from).unwrap()));
+                *self.distances.get_mut(from.x(), from.y()) = Distance::UNREACHED;
+                unreached.push(from);
+            }
+        }
+        while let Some((location, distance)) = stack.pop() {
+            let terrain = world.terrain_at(&location);
+            for &direction in world.directions() {
+                let Some(from) = world.neighbour(&location, &direction.opposite()) else { continue };
+                let Some(from_distance) = self.distance(&from) else { continue };
+                if step_cost(world.topology(), terrain, &direction).is_some_and(|step| from_distance == distance + step) {
+                    stack.push((from, from_distance));
+                    *self.distances.get_mut(from.x(), from.y()) = Distance::UNREACHED;
+                    unreached.push(from);
+                }
+            }
+        }
+
+        let mut queue = BinaryHeap::new();
+        for location in &unreached {
+            self.reach_again(world, location, &mut queue);
+        }
+        self.propagate(world, queue);
+    }
+
+    /// Gives an unreached cell the distance it has as a target or through its reached neighbours, if any.
+    fn reach_again(&mut self, world: &World, location: &Location, queue: &mut BinaryHeap<Reverse<(u32, usize)>>) {
+        if world.terrain_at(location).move_cost().is_none() { return }
+        if self.targets.iter().any(|target| target.index() == location.index()) {
+            self.lower(location, 0, queue);
+        } else if let Some(distance) = self.distance_via_neighbours(world, location) {
+            self.lower(location, distance, queue);
+        }
+    }
+
+    /// The cheapest distance to a target by stepping onto a neighbour that has been reached.
+    fn distance_via_neighbours(&self, world: &World, location: &Location) -> Option<u32> {
+        world.directions().iter().copied()
+            .filter_map(|direction| {
+                let next = world.neighbour(location, &direction)?;
+                Some(step_cost(world.topology(), world.terrain_at(&next), &direction)? + self.distance(&next)?)
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Boundary;
+
+    /// Checks that the field, after its updates, has the distances a field built from scratch would.
+    fn assert_matches_rebuild(world: &World, field: &FlowField) {
+        let rebuilt = FlowField::new(world, field.targets(), field.range());
+        for y in 0..world.height() {
+            for x in 0..world.width() {
+                let location = Location::at(x, y, world);
+                assert_eq!(field.distance(&location), rebuilt.distance(&location), "at ({}, {})", x, y);
+            }
+        }
+    }
+
+    /// A world with walls where the rows have a '#'.
+    fn maze(rows: &[&str], topology: Topology, boundary: Boundary) -> World {
+        let mut world = World::new(rows[0].len(), rows.len());
+        world.set_boundary(boundary);
+        world.set_topology(topology).unwrap();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                if cell == '#' { world.set_terrain(&Location::at(x, y, &world), Terrain::Wall).unwrap() }
+            }
+        }
+        world
+    }
+
+    /// Follows the path's steps, checking each can be taken and that they add up to its cost, and returns where it ends.
+    fn follow(world: &World, from: &Location, path: &Path) -> Location {
+        let mut location = Location::at(from.x(), from.y(), world);
+        let mut cost = 0;
+        for direction in &path.steps {
+            location = world.neighbour(&location, direction).expect("the path leaves the world");
+            cost += step_cost(world.topology(), world.terrain_at(&location), direction).expect("the path crosses a wall");
+        }
+        assert_eq!(cost, path.cost);
+        location
+    }
+
+    const MAZE: [&str; 5] = [
+        "..#.....",
+        ".##.###.",
+        "....#...",
+        "###.#.#.",
+        "....#.#.",
+    ];
+
+    #[test]
+    fn paths_find_their_way_through_a_maze() {
+        let world = maze(&MAZE, Topology::VonNeumann, Boundary::Solid);
+        let (from, to) = (Location::at(0, 0, &world), Location::at(7, 4, &world));
+        let path = find_path(&world, &from, &to, 1000).unwrap();
+        assert_eq!(path.cost, 150);
+        assert_eq!(follow(&world, &from, &path).index(), to.index());
+        assert_eq!(FlowField::new(&world, &[to], 1000).distance(&from), Some(path.cost));
+    }
+
+    #[test]
+    fn paths_costing_more_than_the_limit_are_not_found() {
+        let world = maze(&MAZE, Topology::VonNeumann, Boundary::Solid);
+        let (from, to) = (Location::at(0, 0, &world), Location::at(7, 4, &world));
+        assert!(find_path(&world, &from, &to, 149).is_none());
+        assert_eq!(find_path(&world, &from, &to, 150).map(|path| path.cost), Some(150));
+    }
+
+    #[test]
+    fn walled_off_targets_are_unreachable() {
+        let world = maze(&["......", "..###.", "..#.#.", "..###."], Topology::Moore, Boundary::Wrap);
+        let (from, to) = (Location::at(0, 0, &world), Location::at(3, 2, &world));
+        assert!(find_path(&world, &from, &to, u32::MAX).is_none());
+    }
+
+    #[test]
+    fn paths_take_the_short_way_across_a_wrapped_edge() {
+        let rows = ["..........", ".........."];
+        let world = maze(&rows, Topology::Moore, Boundary::Wrap);
+        let (from, to) = (Location::at(1, 1, &world), Location::at(8, 1, &world));
+        let path = find_path(&world, &from, &to, 1000).unwrap();
+        assert_eq!(path.cost, 30);
+        assert_eq!(follow(&world, &from, &path).index(), to.index());
+        assert!(path.steps.iter().all(|direction| direction.index() == Direction::West.index()));
+
+        let world = maze(&rows, Topology::Moore, Boundary::Solid);
+        assert_eq!(find_path(&world, &from, &to, 1000).map(|path| path.cost), Some(70));
+    }
+
+    #[test]
+    fn walls_reroute_and_reopen_the_field() {
+        let mut world = World::new(10, 5);
+        world.set_boundary(Boundary::Solid);
+        world.set_topology(Topology::VonNeumann).unwrap();
+        let mut field = FlowField::new(&world, &[Location::at(0, 2, &world)], 1000);
+        let far = Location::at(9, 2, &world);
+        assert_eq!(field.distance(&far), Some(90));
+
+        // A wall across the middle, leaving a gap at the bottom.
+        for y in 0..4 {
+            let location = Location::at(5, y, &world);
+            world.set_terrain(&location, Terrain::Wall).unwrap();
+            field.update_terrain(&world, &location);
+        }
+        assert_eq!(field.distance(&far), Some(130));
+        assert_eq!(field.distance(&Location::at(5, 1, &world)), None);
+        assert_matches_rebuild(&world, &field);
+
+        let location = Location::at(5, 2, &world);
+        world.set_terrain(&location, Terrain::Open).unwrap();
+        field.update_terrain(&world, &location);
+        assert_eq!(field.distance(&far), Some(90));
+        assert_matches_rebuild(&world, &field);
+    }
+
+    #[test]
+    fn targets_can_be_added_and_removed() {
+        let world = World::new(12, 12);
+        let left = Location::at(1, 6, &world);
+        let right = Location::at(10, 6, &world);
+        let mut field = FlowField::new(&world, &[left], 1000);
+        assert_eq!(field.distance(&Location::at(9, 6, &world)), Some(40));
+
+        field.add_target(&world, &right);
+        assert_eq!(field.distance(&Location::at(9, 6, &world)), Some(10));
+        assert_eq!(field.direction(&world, &Location::at(9, 6, &world)).map(|d| d.index()), Some(Direction::East.index()));
+        assert_matches_rebuild(&world, &field);
+
+        field.remove_target(&world, &left);
+        assert_eq!(field.distance(&left), Some(30));
+        assert_matches_rebuild(&world, &field);
+    }
+
+    #[test]
+    fn random_changes_match_a_rebuild() {
+        for topology in Topology::ALL {
+            let mut world = World::new(16, 12);
+            world.set_topology(topology).unwrap();
+            let mut field = FlowField::new(&world, &[Location::at(3, 3, &world)], 80);
+            // A fixed linear congruential generator, so the test doesn't touch the shared random buffer.
+            let mut state = 12345u64;
+            let mut next = |bound: usize| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 33) as usize % bound
+            };
+            for _ in 0..300 {
+                let location = Location::at(next(16), next(12), &world);
+                match next(4) {
+                    0 => field.add_target(&world, &location),
+                    1 => field.remove_target(&world, &location),
+                    _ => {
+                        let terrain = if world.terrain_at(&location) == Terrain::Wall { Terrain::Open } else { Terrain::Wall };
+                        world.set_terrain(&location, terrain).unwrap();
+                        field.update_terrain(&world, &location);
+                    }
+                }
+                assert_matches_rebuild(&world, &field);
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Duration, Instant};
+use crate::fps_counter::FrameStats;
+
+/// A timed part of world_processor::step.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Step,
+    CleanUp,
+    GetLocations,
+    DetermineActions,
+    ResolveConflicts,
+    DetermineOutcomes,
+    DrawJoin,
+    UpdateAsynchronously,
+    RecordHeatmap,
+    RecordEvents,
+    RecordHistory,
+    ApplyOutcomes,
+    CollectStats,
+    Draw,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 14] = [
+        Phase::Step,
+        Phase::CleanUp,
+        Phase::GetLocations,
+        Phase::DetermineActions,
+        Phase::ResolveConflicts,
+        Phase::DetermineOutcomes,
+        Phase::DrawJoin,
+        Phase::UpdateAsynchronously,
+        Phase::RecordHeatmap,
+        Phase::RecordEvents,
+        Phase::RecordHistory,
+        Phase::ApplyOutcomes,
+        Phase::CollectStats,
+        Phase::Draw,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Step => "step",
+            Phase::CleanUp => "clean_up",
+            Phase::GetLocations => "get_locations_for_processing",
+            Phase::DetermineActions => "determine_actions",
+            Phase::ResolveConflicts => "resolve_conflicts",
+            Phase::DetermineOutcomes => "determine_outcomes",
+            Phase::DrawJoin => "draw_join",
+            Phase::UpdateAsynchronously => "update_asynchronously",
+            Phase::RecordHeatmap => "record_heatmap",
+            Phase::RecordEvents => "record_events",
+            Phase::RecordHistory => "record_history",
+            Phase::ApplyOutcomes => "apply_outcomes",
+            Phase::CollectStats => "collect_stats",
+            Phase::Draw => "draw",
+        }
+    }
+
+    /// The thread the phase runs on, as numbered in exported traces.
+    fn thread_id(&self) -> u32 {
+        match self {
+            Phase::Draw => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Span {
+    phase: Phase,
+    /// Time from the profiler's creation to the start of the span.
+    start: Duration,
+    duration: Duration,
+}
+
+/// All spans recorded during one tick.
+pub struct TickProfile {
+    tick: u64,
+    spans: Vec<Span>,
+}
+
+impl TickProfile {
+    pub fn duration_of(&self, phase: Phase) -> Option<Duration> {
+        self.spans.iter().find(|span| span.phase == phase).map(|span| span.duration)
+    }
+}
+
+/// Records phase timings for a rolling window of the most recent ticks.
+pub struct Profiler {
+    epoch: Instant,
+    window: usize,
+    ticks: VecDeque<TickProfile>,
+    current: Option<TickProfile>,
+}
+
+impl Profiler {
+    pub fn new(window: usize) -> Profiler {
+        Profiler {
+            epoch: Instant::now(),
+            window: window.max(1),
+            ticks: VecDeque::with_capacity(window.max(1)),
+            current: None,
+        }
+    }
+
+    pub fn begin_tick(&mut self, tick: u64) {
+        self.current = Some(TickProfile { tick, spans: Vec::new() });
+    }
+
+    pub fn end_tick(&mut self) {
+        if let Some(profile) = self.current.take() {
+            if self.ticks.len() == self.window {
+                self.ticks.pop_front();
+            }
+            self.ticks.push_back(profile);
+        }
+    }
+
+    /// Records a span of the current tick that ran from start to end.
+    pub fn record(&mut self, phase: Phase, start: Instant, end: Instant) {
+        let span = Span {
+            phase,
+            start: start.saturating_duration_since(self.epoch),
+            duration: end.saturating_duration_since(start),
+        };
+        if let Some(profile) = self.current.as_mut() {
+            profile.spans.push(span);
+        }
+    }
+
+    /// Runs the phase, recording how long it took.
+    pub fn time<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start, Instant::now());
+        result
+    }
+
+    #[allow(unused)]
+    pub fn latest(&self) -> Option<&TickProfile> {
+        self.ticks.back()
+    }
+
+    /// Statistics of the phase's duration over the ticks in the window in which it ran.
+    pub fn stats(&self, phase: Phase) -> FrameStats {
+        let mut durations: Vec<Duration> = self.ticks.iter()
+            .filter_map(|profile| profile.duration_of(phase))
+            .collect();
+        FrameStats::from_frame_times(&mut durations)
+    }
+
+    /// Writes one row per recorded span: tick, phase, thread, start and duration in microseconds.
+    pub fn write_csv(&self, path: &str) -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "tick,phase,thread,start_us,duration_us")?;
+        for profile in &self.ticks {
+            for span in &profile.spans {
+                writeln!(
+                    out, "{},{},{},{:.3},{:.3}",
+                    profile.tick, span.phase.name(), span.phase.thread_id(),
+                    micros(span.start), micros(span.duration),
+                )?;
+            }
+        }
+        out.flush()
+    }
+
+    /// Writes the recorded spans in the Chrome trace-event format, viewable in chrome://tracing or Perfetto.
+    pub fn write_chrome_trace(&self, path: &str) -> std::io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{{\"traceEvents\":[")?;
+        writeln!(out, "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{{\"name\":\"step\"}}}},")?;
+        write!(out, "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{{\"name\":\"draw\"}}}}")?;
+        for profile in &self.ticks {
+            for span in &profile.spans {
+                write!(
+                    out, ",\n{{\"name\":\"{}\",\"cat\":\"step\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":{},\"args\":{{\"tick\":{}}}}}",
+                    span.phase.name(), micros(span.start), micros(span.duration), span.phase.thread_id(), profile.tick,
+                )?;
+            }
+        }
+        writeln!(out, "\n]}}")?;
+        out.flush()
+    }
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
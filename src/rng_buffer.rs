@@ -1,45 +1,61 @@
-use rand::random;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use rand::{random, Rng, SeedableRng};
+use rand::rngs::StdRng;
 
 const BUFFER_SIZE_U16: usize = 65536;
-static mut BUFFER_U16: [f64; BUFFER_SIZE_U16] = [0.0; BUFFER_SIZE_U16];
-static mut NEXT: u16 = 0;
+/// The bits of each f64 value. Values are atomic so that refilling the buffer is never a data race, though values
+/// taken while it is being refilled may be from either sequence.
+static BUFFER_U16: [AtomicU64; BUFFER_SIZE_U16] = [const { AtomicU64::new(0) }; BUFFER_SIZE_U16];
+/// Taken from many threads at once, so each call to next gets its own position in the buffer.
+static NEXT: AtomicU16 = AtomicU16::new(0);
+static RNG: Mutex<Option<StdRng>> = Mutex::new(None);
 
 pub fn init() {
-    unsafe {
-        for i in 0..BUFFER_SIZE_U16 {
-            BUFFER_U16[i] = random();
-        }
+    init_with_seed(random());
+}
+
+/// Fills the buffer from the given seed, so that the same sequence of values is generated each run.
+/// The sequence is only the same if nothing else is using rng_buffer meanwhile.
+pub fn init_with_seed(seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    for value in &BUFFER_U16 {
+        value.store(rng.gen::<f64>().to_bits(), Ordering::Relaxed);
     }
+    NEXT.store(0, Ordering::Relaxed);
+    *RNG.lock().unwrap() = Some(rng);
 }
 
+/// Replaces the next value with a newly generated one before returning it.
+/// Only call while loading, when nothing else is using rng_buffer, or the values others take are unpredictable.
 pub fn generate_next() -> f64 {
-    increment();
-    regenerate();
-    read()
+    let index = increment();
+    regenerate(index);
+    read(index)
 }
 
 pub fn next() -> f64 {
-    increment();
-    read()
+    read(increment())
 }
 
-fn increment() {
-    // Safety: TODO - Needs testing.
-    unsafe {
-        NEXT = NEXT.wrapping_add(1);
-    }
+/// Moves on to the next position in the buffer, returning it.
+fn increment() -> usize {
+    NEXT.fetch_add(1, Ordering::Relaxed).wrapping_add(1) as usize
 }
 
-fn read() -> f64 {
-    // Safety: We don't care about data races here, as we are reading random values.
-    unsafe {
-        BUFFER_U16[NEXT as usize]
-    }
+fn read(index: usize) -> f64 {
+    f64::from_bits(BUFFER_U16[index].load(Ordering::Relaxed))
 }
 
-fn regenerate() {
-    // Safety: We don't care about data races here, as we are writing random values.
-    unsafe {
-        BUFFER_U16[NEXT as usize] = random();
-    }
+fn regenerate(index: usize) {
+    let value: f64 = RNG.lock().unwrap().as_mut().expect("rng_buffer should be initialised").gen();
+    BUFFER_U16[index].store(value.to_bits(), Ordering::Relaxed);
+}
+
+/// Held by tests that use rng_buffer or the world processor, which every test in the process shares,
+/// so that tests comparing results from the same seed aren't thrown off by others taking values meanwhile.
+#[cfg(test)]
+pub(crate) fn lock_for_test() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|error| error.into_inner())
 }
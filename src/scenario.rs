@@ -0,0 +1,374 @@
+//! Plain-text descriptions of a world's initial setup, so experiments can be shared and repeated.
+//!
+//! Each line is a keyword followed by its values, with `#` starting a comment:
+//!
+//! ```text
+//! size 1800 900
+//! boundary solid                # wrap (the default) or solid
+//! topology hex                  # moore (the default), von-neumann or hex; hex worlds need an even height
+//! duration move 2               # steps that wait, move, turn or blocked outcomes take (default 1 each)
+//! seed 42                       # optional; random if left out
+//! wall rect 850 0 100 400       # x y width height
+//! wall circle 400 450 100       # centre-x centre-y radius
+//! spawn rect 0 0 800 900 count 20000 behaviour cruiser facing east color red
+//! spawn circle 1400 450 300 count 10000 interval 5
+//! ```
+//!
+//! Spawn options may come in any order after the region: `count` is required, while `behaviour`
+//! (wanderer, cruiser, idler) and `facing` (north, northeast, ..., one of the topology's) are random if left out.
+//! `color` (red, orange, yellow, green, cyan, blue, purple, magenta) sets the entities' lineage,
+//! which the lineage colour mode draws in that colour; otherwise lineage is the index of the spawn line.
+//! `interval` (1 to 255, default 1) is how many ticks each step of the entities' actions takes, so that
+//! entities with an interval of 5 act at most every 5th tick, and take 10 ticks over an outcome with a duration of 2.
+//! Their first actions are spread randomly over the first interval ticks.
+//!
+//! Walls are built before any entities are spawned, and spawns are made in the order given.
+
+use std::fs;
+use crate::action::ActionDurations;
+use crate::behaviour::Behaviour;
+use crate::entity::Entity;
+use crate::rng_buffer;
+use crate::topology::Topology;
+use crate::world::{Boundary, Direction, Location, Terrain, World};
+
+/// How many random locations are tried per entity before a spawn region is considered full.
+const PLACEMENT_ATTEMPTS: u32 = 100;
+
+/// Names of the colours used by the lineage colour mode, in lineage order.
+const COLOR_NAMES: [&str; 8] = ["red", "orange", "yellow", "green", "cyan", "blue", "purple", "magenta"];
+
+pub struct Scenario {
+    pub width: usize,
+    pub height: usize,
+    pub boundary: Boundary,
+    pub topology: Topology,
+    pub action_durations: ActionDurations,
+    pub seed: Option<u64>,
+    pub walls: Vec<Region>,
+    pub spawns: Vec<Spawn>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Region {
+    Rect { x: usize, y: usize, width: usize, height: usize },
+    Circle { x: usize, y: usize, radius: usize },
+}
+
+pub struct Spawn {
+    pub region: Region,
+    pub count: u32,
+    /// Random for each entity if None.
+    pub behaviour: Option<Behaviour>,
+    /// Random for each entity if None.
+    pub facing: Option<Direction>,
+    pub lineage: u32,
+    pub interval: u8,
+}
+
+impl Region {
+    /// The smallest rectangle containing the region, as (left, top, right, bottom) with right and bottom exclusive.
+    fn bounds(&self) -> (usize, usize, usize, usize) {
+        self.checked_bounds().expect("regions should be checked when parsed")
+    }
+
+    /// As bounds, or None if the region is too large to work with, including the distances compared by contains.
+    fn checked_bounds(&self) -> Option<(usize, usize, usize, usize)> {
+        match *self {
+            Region::Rect { x, y, width, height } => Some((x, y, x.checked_add(width)?, y.checked_add(height)?)),
+            Region::Circle { x, y, radius } => {
+                radius.checked_mul(radius)?.checked_mul(2)?;
+                let (right, bottom) = (x.checked_add(radius)?.checked_add(1)?, y.checked_add(radius)?.checked_add(1)?);
+                Some((x.saturating_sub(radius), y.saturating_sub(radius), right, bottom))
+            }
+        }
+    }
+
+    fn contains(&self, px: usize, py: usize) -> bool {
+        match *self {
+            Region::Rect { x, y, width, height } => px >= x && px < x + width && py >= y && py < y + height,
+            Region::Circle { x, y, radius } => {
+                let dx = px.abs_diff(x);
+                let dy = py.abs_diff(y);
+                dx * dx + dy * dy <= radius * radius
+            }
+        }
+    }
+
+    /// The bounds clipped to the world, or None if the region lies entirely outside it.
+    fn bounds_within(&self, world: &World) -> Option<(usize, usize, usize, usize)> {
+        let (left, top, right, bottom) = self.bounds();
+        let (right, bottom) = (right.min(world.width()), bottom.min(world.height()));
+        (left < right && top < bottom).then_some((left, top, right, bottom))
+    }
+}
+
+pub fn load(path: &str) -> Result<Scenario, String> {
+    let text = fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    parse(&text).map_err(|error| format!("{}: {}", path, error))
+}
+
+pub fn parse(text: &str) -> Result<Scenario, String> {
+    let mut scenario = Scenario {
+        width: 0,
+        height: 0,
+        boundary: Boundary::Wrap,
+        topology: Topology::Moore,
+        action_durations: ActionDurations::default(),
+        seed: None,
+        walls: Vec::new(),
+        spawns: Vec::new(),
+    };
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() { continue }
+        scenario.parse_line(&words).map_err(|error| format!("line {}: {}", i + 1, error))?;
+    }
+    if scenario.width == 0 || scenario.height == 0 {
+        return Err(String::from("missing size"));
+    }
+    Ok(scenario)
+}
+
+impl Scenario {
+    fn parse_line(&mut self, words: &[&str]) -> Result<(), String> {
+        match words {
+            ["size", width, height] => {
+                self.width = parse_number(width)?;
+                self.height = parse_number(height)?;
+            }
+            ["boundary", "wrap"] => self.boundary = Boundary::Wrap,
+            ["boundary", "solid"] => self.boundary = Boundary::Solid,
+            ["topology", name] => self.topology = Topology::from_name(name)
+                .ok_or(format!("expected moore, von-neumann or hex but found '{}'", name))?,
+            ["duration", name, steps] => {
+                let duration = self.action_durations.get_mut(name).ok_or(format!(
+                    "expected {} but found '{}'", ActionDurations::NAMES.join(", "), name,
+                ))?;
+                *duration = parse_number(steps)?;
+                if *duration == 0 {
+                    return Err(String::from("durations must be at least 1"));
+                }
+            }
+            ["seed", seed] => self.seed = Some(parse_number(seed)?),
+            ["wall", region @ ..] => {
+                let (region, rest) = parse_region(region)?;
+                if !rest.is_empty() {
+                    return Err(format!("unexpected '{}'", rest[0]));
+                }
+                self.walls.push(region);
+            }
+            ["spawn", region @ ..] => {
+                let (region, options) = parse_region(region)?;
+                let spawn = self.parse_spawn(region, options)?;
+                self.spawns.push(spawn);
+            }
+            _ => return Err(format!("can't understand '{}'", words.join(" "))),
+        }
+        Ok(())
+    }
+
+    fn parse_spawn(&self, region: Region, options: &[&str]) -> Result<Spawn, String> {
+        let mut spawn = Spawn {
+            region,
+            count: 0,
+            behaviour: None,
+            facing: None,
+            lineage: self.spawns.len() as u32,
+            interval: 1,
+        };
+        let mut has_count = false;
+        for option in options.chunks(2) {
+            match option {
+                ["count", count] => {
+                    spawn.count = parse_number(count)?;
+                    has_count = true;
+                }
+                ["behaviour", "random"] => spawn.behaviour = None,
+                ["behaviour", name] => spawn.behaviour = Some(parse_behaviour(name)?),
+                ["facing", "random"] => spawn.facing = None,
+                ["facing", name] => spawn.facing = Some(parse_direction(name)?),
+                ["interval", interval] => {
+                    spawn.interval = parse_number(interval)?;
+                    if spawn.interval == 0 {
+                        return Err(String::from("interval must be at least 1"));
+                    }
+                }
+                ["color", name] => {
+                    let index = COLOR_NAMES.iter().position(|color| color == name)
+                        .ok_or_else(|| format!("unknown color '{}'", name))?;
+                    spawn.lineage = index as u32;
+                }
+                _ => return Err(format!("can't understand spawn option '{}'", option.join(" "))),
+            }
+        }
+        if !has_count {
+            return Err(String::from("spawn is missing a count"));
+        }
+        Ok(spawn)
+    }
+
+    /// Builds the world the scenario describes, drawing random values from rng_buffer.
+    /// Seeding rng_buffer with the scenario's seed first is left to the caller.
+    pub fn build(&self) -> Result<World, String> {
+        let mut world = World::new(self.width, self.height);
+        world.set_boundary(self.boundary);
+        world.set_topology(self.topology)?;
+        world.set_action_durations(self.action_durations);
+
+        for wall in &self.walls {
+            let Some((left, top, right, bottom)) = wall.bounds_within(&world) else { continue };
+            for y in top..bottom {
+                for x in left..right {
+                    if wall.contains(x, y) {
+                        world.set_terrain(&Location::at(x, y, &world), Terrain::Wall)?;
+                    }
+                }
+            }
+        }
+
+        for (i, spawn) in self.spawns.iter().enumerate() {
+            spawn_entities(&mut world, spawn).map_err(|error| format!("spawn {}: {}", i + 1, error))?;
+        }
+        Ok(world)
+    }
+}
+
+fn spawn_entities(world: &mut World, spawn: &Spawn) -> Result<(), String> {
+    if let Some(facing) = spawn.facing.filter(|facing| !world.topology().has(facing)) {
+        return Err(format!("{:?} isn't a direction in a {} world", facing, world.topology().name()));
+    }
+    let Some((left, top, right, bottom)) = spawn.region.bounds_within(world) else {
+        return if spawn.count == 0 { Ok(()) } else { Err(String::from("region is outside the world")) };
+    };
+
+    let mut count = 0;
+    let mut attempts = 0;
+    while count < spawn.count {
+        if attempts == spawn.count.saturating_mul(PLACEMENT_ATTEMPTS) {
+            return Err(format!("only found room for {} of {} entities", count, spawn.count));
+        }
+        attempts += 1;
+
+        let x = left + (rng_buffer::generate_next() * (right - left) as f64) as usize;
+        let y = top + (rng_buffer::next() * (bottom - top) as f64) as usize;
+        if !spawn.region.contains(x, y) { continue }
+
+        let mut entity = Entity::new(x, y, world);
+        entity.behaviour = spawn.behaviour.unwrap_or_else(Behaviour::random);
+        entity.facing = spawn.facing.unwrap_or(entity.facing);
+        entity.lineage = spawn.lineage;
+        entity.interval = spawn.interval;
+        // Spread out when the entities of a spawn act, rather than all acting on the same ticks.
+        if spawn.interval > 1 {
+            entity.cooldown = (rng_buffer::next() * spawn.interval as f64) as u16;
+        }
+        if world.place_entity(entity).is_ok() {
+            count += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Parses a region from the start of the words, returning it with the words after it.
+fn parse_region<'a, 'b>(words: &'a [&'b str]) -> Result<(Region, &'a [&'b str]), String> {
+    let (region, rest) = match words {
+        ["rect", x, y, width, height, rest @ ..] => Ok((Region::Rect {
+            x: parse_number(x)?,
+            y: parse_number(y)?,
+            width: parse_number(width)?,
+            height: parse_number(height)?,
+        }, rest)),
+        ["circle", x, y, radius, rest @ ..] => Ok((Region::Circle {
+            x: parse_number(x)?,
+            y: parse_number(y)?,
+            radius: parse_number(radius)?,
+        }, rest)),
+        _ => Err(String::from("expected 'rect x y width height' or 'circle x y radius'")),
+    }?;
+    region.checked_bounds().ok_or("region is too large")?;
+    Ok((region, rest))
+}
+
+fn parse_number<T: std::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse().map_err(|_| format!("'{}' is not a valid number", word))
+}
+
+fn parse_behaviour(name: &str) -> Result<Behaviour, String> {
+    match name {
+        "wanderer" => Ok(Behaviour::Wanderer),
+        "cruiser" => Ok(Behaviour::Cruiser),
+        "idler" => Ok(Behaviour::Idler),
+        _ => Err(format!("unknown behaviour '{}'", name)),
+    }
+}
+
+fn parse_direction(name: &str) -> Result<Direction, String> {
+    match name {
+        "north" => Ok(Direction::North),
+        "northeast" => Ok(Direction::Northeast),
+        "east" => Ok(Direction::East),
+        "southeast" => Ok(Direction::Southeast),
+        "south" => Ok(Direction::South),
+        "southwest" => Ok(Direction::Southwest),
+        "west" => Ok(Direction::West),
+        "northwest" => Ok(Direction::Northwest),
+        _ => Err(format!("unknown facing '{}'", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_of(text: &str) -> String {
+        parse(text).err().expect("scenario should not parse")
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        assert_eq!(error_of("seed 1\n"), "missing size");
+        assert_eq!(error_of("size 10 10\n\nsize ten 10\n"), "line 3: 'ten' is not a valid number");
+        assert_eq!(error_of("size 10 10\nboundary bouncy\n"), "line 2: can't understand 'boundary bouncy'");
+        assert_eq!(error_of("size 10 10 # comment\ntopology square\n"),
+            "line 2: expected moore, von-neumann or hex but found 'square'");
+        assert_eq!(error_of("size 10 10\nduration wait 0\n"), "line 2: durations must be at least 1");
+        assert_eq!(error_of("size 10 10\nwall rect 1 2 3\n"),
+            "line 2: expected 'rect x y width height' or 'circle x y radius'");
+        assert_eq!(error_of("size 10 10\nwall circle 1 2 3 extra\n"), "line 2: unexpected 'extra'");
+    }
+
+    #[test]
+    fn regions_too_large_to_work_with_are_rejected() {
+        let max = usize::MAX;
+        assert_eq!(error_of(&format!("size 10 10\nwall rect {} 0 1 1\n", max)), "line 2: region is too large");
+        assert_eq!(error_of(&format!("size 10 10\nwall rect 0 1 1 {}\n", max)), "line 2: region is too large");
+        assert_eq!(error_of(&format!("size 10 10\nwall circle {} 0 0\n", max)), "line 2: region is too large");
+        assert_eq!(error_of(&format!("size 10 10\nspawn circle 5 5 {} count 1\n", 1usize << (usize::BITS / 2))),
+            "line 2: region is too large");
+        assert!(parse(&format!("size 10 10\nwall circle 5 5 {}\n", 1usize << (usize::BITS / 2 - 2))).is_ok());
+    }
+
+    #[test]
+    fn spawn_errors() {
+        assert_eq!(error_of("size 10 10\nspawn rect 0 0 5 5\n"), "line 2: spawn is missing a count");
+        assert_eq!(error_of("size 10 10\nspawn rect 0 0 5 5 count 3 interval 0\n"),
+            "line 2: interval must be at least 1");
+        assert_eq!(error_of("size 10 10\nspawn rect 0 0 5 5 count 3 behaviour sleeper\n"),
+            "line 2: unknown behaviour 'sleeper'");
+        assert_eq!(error_of("size 10 10\nspawn rect 0 0 5 5 count 3 colour red\n"),
+            "line 2: can't understand spawn option 'colour red'");
+        assert_eq!(error_of("size 10 10\nspawn rect 0 0 5 5 count 3 color mauve\n"),
+            "line 2: unknown color 'mauve'");
+    }
+
+    #[test]
+    fn odd_hex_worlds_fail_to_build() {
+        let scenario = parse("size 10 9\ntopology hex\n").unwrap();
+        assert_eq!(
+            scenario.build().err().unwrap(),
+            "a hex world can't be 9 high, as it must have an even number of rows to wrap",
+        );
+    }
+}
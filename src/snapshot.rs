@@ -0,0 +1,245 @@
+//! Saving and loading the complete state of a World, along with the tick it was taken at.
+//!
+//! The boundary mode, topology, action durations and wall locations are stored after the world size.
+//! Entities are stored in id order, so ids are the same after loading.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use crate::action::{ActionDurations, Outcome};
+use crate::behaviour::Behaviour;
+use crate::binary_io::*;
+use crate::entity::{Entity, EntityRef};
+use crate::topology::Topology;
+use crate::world::{Boundary, Direction, Location, Terrain, World};
+
+const MAGIC: &[u8] = b"GTSNAP05";
+
+/// The most cells a loaded world may have. Only chunks with something in them are allocated, but the table of
+/// chunks is allocated up front, so this keeps a corrupt size from taking gigabytes while allowing worlds larger
+/// than scenarios/vast.txt.
+const MAX_CELLS: usize = 1 << 34;
+
+pub fn save(path: &str, world: &World, tick: u64) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out, world, tick)?;
+    out.flush()
+}
+
+pub fn load(path: &str) -> Result<(World, u64), String> {
+    let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+    read(&mut BufReader::new(file)).map_err(|error| format!("{}: {}", path, error))
+}
+
+pub fn write(out: &mut impl Write, world: &World, tick: u64) -> std::io::Result<()> {
+    out.write_all(MAGIC)?;
+    write_u32(out, world.width() as u32)?;
+    write_u32(out, world.height() as u32)?;
+    write_u8(out, boundary_code(world.boundary()))?;
+    write_u8(out, world.topology().index() as u8)?;
+    out.write_all(&world.action_durations().to_array())?;
+    write_u32(out, world.wall_count() as u32)?;
+    for wall in world.walls() {
+        write_u32(out, wall.x() as u32)?;
+        write_u32(out, wall.y() as u32)?;
+    }
+    write_u64(out, tick)?;
+    write_u32(out, world.entity_count() as u32)?;
+    for id in 0..world.entity_count() {
+        write_entity(out, world.entity(id).unwrap())?;
+    }
+    Ok(())
+}
+
+/// The world is only allocated once its size has been checked against the walls and entities it should hold.
+pub fn read(input: &mut impl Read) -> Result<(World, u64), String> {
+    expect_magic(input, MAGIC)?;
+    let width = read_u32(input).map_err(describe)? as usize;
+    let height = read_u32(input).map_err(describe)? as usize;
+    let cells = width.checked_mul(height).filter(|cells| (1..=MAX_CELLS).contains(cells))
+        .ok_or_else(|| format!("a world can't be {}x{}", width, height))?;
+
+    let boundary = boundary_of_code(read_u8(input).map_err(describe)?).ok_or("invalid boundary")?;
+    let topology = Topology::from_index(read_u8(input).map_err(describe)? as usize).ok_or("invalid topology")?;
+    let mut durations = [0; 4];
+    input.read_exact(&mut durations).map_err(describe)?;
+    let wall_count = read_u32(input).map_err(describe)? as usize;
+    if wall_count > cells {
+        return Err(format!("{} walls don't fit in a {}x{} world", wall_count, width, height));
+    }
+    let mut walls = Vec::new();
+    for _ in 0..wall_count {
+        let x = read_u32(input).map_err(describe)? as usize;
+        let y = read_u32(input).map_err(describe)? as usize;
+        if x >= width || y >= height {
+            return Err(format!("wall at ({}, {}) is outside the world", x, y));
+        }
+        walls.push((x, y));
+    }
+    let tick = read_u64(input).map_err(describe)?;
+    let entity_count = read_u32(input).map_err(describe)?;
+    if entity_count as usize > cells - wall_count {
+        return Err(format!(
+            "{} entities don't fit in a {}x{} world with {} walls", entity_count, width, height, wall_count,
+        ));
+    }
+
+    let mut world = World::new(width, height);
+    world.set_boundary(boundary);
+    world.set_topology(topology)?;
+    world.set_action_durations(ActionDurations::from_array(durations));
+    for (x, y) in walls {
+        world.set_terrain(&Location::at(x, y, &world), Terrain::Wall)?;
+    }
+    for id in 0..entity_count {
+        let entity = read_entity(input, &world).map_err(|error| format!("entity {}: {}", id, error))?;
+        world.place_entity(entity).map_err(|error| format!("entity {}: {}", id, error))?;
+    }
+    Ok((world, tick))
+}
+
+fn boundary_code(boundary: Boundary) -> u8 {
+    match boundary {
+        Boundary::Wrap => 0,
+        Boundary::Solid => 1,
+    }
+}
+
+fn boundary_of_code(code: u8) -> Option<Boundary> {
+    match code {
+        0 => Some(Boundary::Wrap),
+        1 => Some(Boundary::Solid),
+        _ => None,
+    }
+}
+
+fn write_entity(out: &mut impl Write, entity: EntityRef) -> std::io::Result<()> {
+    write_u32(out, entity.location().x() as u32)?;
+    write_u32(out, entity.location().y() as u32)?;
+    write_u8(out, entity.facing().index() as u8)?;
+    write_u8(out, entity.behaviour().index() as u8)?;
+    write_u32(out, entity.lineage())?;
+    write_u32(out, entity.age())?;
+    write_f32(out, entity.energy())?;
+    write_u8(out, entity.last_outcome().to_code())?;
+    write_u8(out, entity.interval())?;
+    write_u32(out, entity.cooldown() as u32)
+}
+
+fn read_entity(input: &mut impl Read, world: &World) -> Result<Entity, String> {
+    let x = read_u32(input).map_err(describe)? as usize;
+    let y = read_u32(input).map_err(describe)? as usize;
+    if x >= world.width() || y >= world.height() {
+        return Err(format!("location ({}, {}) is outside the world", x, y));
+    }
+    let facing = read_u8(input).map_err(describe)?;
+    let behaviour = read_u8(input).map_err(describe)?;
+
+    let mut entity = Entity::new(x, y, world);
+    entity.facing = Direction::from_index(facing as usize).ok_or("invalid facing")?;
+    if !world.topology().has(&entity.facing) {
+        return Err(format!("{:?} isn't a direction in a {} world", entity.facing, world.topology().name()));
+    }
+    entity.behaviour = Behaviour::from_index(behaviour as usize).ok_or("invalid behaviour")?;
+    entity.lineage = read_u32(input).map_err(describe)?;
+    entity.age = read_u32(input).map_err(describe)?;
+    entity.energy = read_f32(input).map_err(describe)?;
+    let last_outcome = read_u8(input).map_err(describe)?;
+    entity.last_outcome = Outcome::from_code(last_outcome).ok_or("invalid last outcome")?;
+    entity.interval = read_u8(input).map_err(describe)?;
+    if entity.interval == 0 {
+        return Err(String::from("interval is zero"));
+    }
+    let cooldown = read_u32(input).map_err(describe)?;
+    entity.cooldown = u16::try_from(cooldown).map_err(|_| format!("cooldown {} is too long", cooldown))?;
+    Ok(entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng_buffer;
+
+    fn sample_world() -> World {
+        let _lock = rng_buffer::lock_for_test();
+        let mut world = World::new(20, 10);
+        world.set_boundary(Boundary::Solid);
+        world.set_topology(Topology::Hex).unwrap();
+        world.set_action_durations(ActionDurations { wait: 2, moving: 3, turn: 1, blocked: 5 });
+        world.set_terrain(&Location::at(4, 4, &world), Terrain::Wall).unwrap();
+        world.set_terrain(&Location::at(19, 9, &world), Terrain::Wall).unwrap();
+
+        let mut entity = Entity::new(1, 2, &world);
+        entity.facing = Direction::Southwest;
+        entity.behaviour = Behaviour::Cruiser;
+        entity.lineage = 7;
+        entity.age = 120;
+        entity.energy = 0.25;
+        entity.last_outcome = Outcome::Move(Direction::East);
+        entity.interval = 3;
+        entity.cooldown = 2;
+        world.place_entity(entity).unwrap();
+
+        let mut entity = Entity::new(18, 9, &world);
+        entity.behaviour = Behaviour::Idler;
+        entity.last_outcome = Outcome::Blocked;
+        world.place_entity(entity).unwrap();
+        world
+    }
+
+    fn to_bytes(world: &World, tick: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write(&mut bytes, world, tick).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip_keeps_the_world() {
+        let world = sample_world();
+        let bytes = to_bytes(&world, 42);
+        let (loaded, tick) = read(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(tick, 42);
+        assert_eq!((loaded.width(), loaded.height()), (20, 10));
+        assert_eq!(loaded.boundary(), Boundary::Solid);
+        assert_eq!(loaded.topology(), Topology::Hex);
+        assert_eq!(loaded.action_durations(), world.action_durations());
+        let walls: Vec<_> = loaded.walls().iter().map(|wall| (wall.x(), wall.y())).collect();
+        assert_eq!(walls, [(4, 4), (19, 9)]);
+        assert_eq!(loaded.entity_count(), 2);
+
+        let entity = loaded.entity(0).unwrap();
+        assert_eq!((entity.location().x(), entity.location().y()), (1, 2));
+        assert_eq!(entity.facing().index(), Direction::Southwest.index());
+        assert_eq!(entity.behaviour(), Behaviour::Cruiser);
+        assert_eq!((entity.lineage(), entity.age(), entity.energy()), (7, 120, 0.25));
+        assert_eq!(entity.last_outcome().to_code(), Outcome::Move(Direction::East).to_code());
+        assert_eq!((entity.interval(), entity.cooldown()), (3, 2));
+        assert_eq!(loaded.entity(1).unwrap().behaviour(), Behaviour::Idler);
+
+        assert_eq!(to_bytes(&loaded, 42), bytes);
+    }
+
+    #[test]
+    fn bad_snapshots_are_errors() {
+        let bytes = to_bytes(&sample_world(), 0);
+        assert!(read(&mut &bytes[..bytes.len() - 1]).is_err());
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(read(&mut wrong_magic.as_slice()).is_err());
+    }
+
+    #[test]
+    fn corrupt_sizes_and_counts_are_errors() {
+        let bytes = to_bytes(&sample_world(), 0);
+        let error_with = |offset: usize, value: &[u8]| {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + value.len()].copy_from_slice(value);
+            read(&mut corrupt.as_slice()).err().expect("snapshot should not load")
+        };
+        // The width and height follow the magic, and the entity count follows the two walls and the tick.
+        assert_eq!(error_with(8, &0u32.to_le_bytes()), "a world can't be 0x10");
+        assert_eq!(error_with(8, &[0xff; 8]), "a world can't be 4294967295x4294967295");
+        assert_eq!(error_with(50, &199u32.to_le_bytes()), "199 entities don't fit in a 20x10 world with 2 walls");
+        assert_eq!(error_with(62, &[Direction::North.index() as u8]), "entity 0: North isn't a direction in a hex world");
+    }
+}
@@ -0,0 +1,257 @@
+//! Queries about what is near a location, for sensors, statistics and tooling.
+//! Distances are measured the short way round in wrapping worlds, and stop at the edges of solid ones.
+//! Each query only looks at the cells it covers, so its cost depends on its range rather than the world size.
+
+use std::ops::RangeInclusive;
+use crate::topology::Topology;
+use crate::world::{Boundary, Direction, EntityId, Location, Terrain, World};
+
+/// Which surrounding cells count as neighbours.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Neighbourhood {
+    /// The eight cells sharing an edge or a corner.
+    Moore,
+    /// The four cells sharing an edge.
+    VonNeumann,
+    /// The six cells of a hex world.
+    Hex,
+}
+
+impl Neighbourhood {
+    pub fn directions(&self) -> &'static [Direction] {
+        match self {
+            Neighbourhood::Moore => &Direction::ALL,
+            Neighbourhood::VonNeumann => &[Direction::North, Direction::East, Direction::South, Direction::West],
+            Neighbourhood::Hex => Topology::Hex.directions(),
+        }
+    }
+}
+
+/// What a ray stopped at, and how many steps it took to get there.
+#[derive(Copy, Clone, Debug)]
+pub enum RayHit {
+    Entity(EntityId, usize),
+    Wall(Location, usize),
+    /// A solid boundary, after the given number of steps within the world.
+    Edge(usize),
+    /// Nothing within range, or the ray came back round to where it started.
+    Nothing,
+}
+
+impl World {
+    /// The shortest offset from one location to another, going across the edges if the world wraps.
+    pub fn displacement(&self, from: &Location, to: &Location) -> (isize, isize) {
+        (
+            self.axis_displacement(from.x(), to.x(), self.width()),
+            self.axis_displacement(from.y(), to.y(), self.height()),
+        )
+    }
+
+    fn axis_displacement(&self, from: usize, to: usize, size: usize) -> isize {
+        let offset = to as isize - from as isize;
+        if self.boundary() == Boundary::Solid { return offset }
+        let size = size as isize;
+        let offset = offset.rem_euclid(size);
+        if offset > size / 2 { offset - size } else { offset }
+    }
+
+    /// The entities whose straight-line distance from the center is at most the radius, row by row.
+    pub fn entities_within(&self, center: &Location, radius: usize) -> impl Iterator<Item = EntityId> + '_ {
+        let center = *center;
+        let xs = self.axis_offsets(center.x(), radius, self.width());
+        let ys = self.axis_offsets(center.y(), radius, self.height());
+        let radius_squared = radius.saturating_mul(radius);
+        ys.flat_map(move |dy| xs.clone().map(move |dx| (dx, dy)))
+            .filter(move |(dx, dy)| distance_squared(*dx, *dy) <= radius_squared)
+            .filter_map(move |(dx, dy)| self.get_entity_id(&self.shifted(&center, dx, dy)))
+    }
+
+    /// The entities in the rectangle with the given top left corner and size, row by row.
+    /// The rectangle carries on across the edges if the world wraps, and is cut off at them if not.
+    pub fn entities_in_rect(&self, corner: &Location, width: usize, height: usize) -> impl Iterator<Item = EntityId> + '_ {
+        let corner = *corner;
+        let extent = |start: usize, length: usize, size: usize| match self.boundary() {
+            Boundary::Wrap => length.min(size),
+            Boundary::Solid => length.min(size - start),
+        };
+        let xs = 0..extent(corner.x(), width, self.width()) as isize;
+        let ys = 0..extent(corner.y(), height, self.height()) as isize;
+        ys.flat_map(move |dy| xs.clone().map(move |dx| (dx, dy)))
+            .filter_map(move |(dx, dy)| self.get_entity_id(&self.shifted(&corner, dx, dy)))
+    }
+
+    /// The k entities closest to the point, including any at the point itself, nearest first.
+    /// Entities at the same distance are ordered by id. The search widens one ring of cells at a time,
+    /// so it is fast where entities are dense and slow where the kth nearest is far away.
+    pub fn nearest_entities(&self, point: &Location, k: usize) -> Vec<EntityId> {
+        if k == 0 { return Vec::new() }
+        let xs = self.axis_offsets(point.x(), usize::MAX, self.width());
+        let ys = self.axis_offsets(point.y(), usize::MAX, self.height());
+        let last_ring = [*xs.start(), *xs.end(), *ys.start(), *ys.end()].into_iter()
+            .map(isize::unsigned_abs)
+            .max()
+            .unwrap();
+
+        // (distance squared, id) of every entity found so far.
+        let mut found: Vec<(usize, EntityId)> = Vec::new();
+        for ring in 0..=last_ring {
+            if found.len() >= k {
+                let (_, kth, _) = found.select_nth_unstable(k - 1);
+                // Every cell in this ring and beyond is at least ring cells away.
+                if ring * ring > kth.0 { break }
+            }
+            let ring = ring as isize;
+            for dy in (-ring..=ring).filter(|dy| ys.contains(dy)) {
+                let dxs: Vec<isize> = if dy.abs() == ring { (-ring..=ring).collect() } else { vec![-ring, ring] };
+                for dx in dxs.into_iter().filter(|dx| xs.contains(dx)) {
+                    if let Some(id) = self.get_entity_id(&self.shifted(point, dx, dy)) {
+                        found.push((distance_squared(dx, dy), id));
+                    }
+                }
+            }
+        }
+        found.sort_unstable();
+        found.into_iter().take(k).map(|(_, id)| id).collect()
+    }
+
+    /// How many of the cells around the location have an entity in them.
+    /// In worlds too small for the neighbours to be distinct cells, each cell is only counted once.
+    /// Panics if the neighbourhood has a direction the world's topology doesn't, which would otherwise be
+    /// counted as empty.
+    pub fn count_neighbours(&self, location: &Location, neighbourhood: Neighbourhood) -> usize {
+        assert!(
+            neighbourhood.directions().iter().all(|direction| self.topology().has(direction)),
+            "a {:?} neighbourhood doesn't fit a {} world", neighbourhood, self.topology().name(),
+        );
+        let mut counted = [location.index(); 8];
+        let mut count = 0;
+        for (i, direction) in neighbourhood.directions().iter().enumerate() {
+            let Some(neighbour) = self.neighbour(location, direction) else { continue };
+            if counted.contains(&neighbour.index()) { continue }
+            counted[i] = neighbour.index();
+            if self.get_entity_id(&neighbour).is_some() {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Steps from the location in the given direction until reaching an entity, a wall or a solid boundary,
+    /// for at most range steps. Panics if the direction isn't one of the world's topology, rather than
+    /// reporting an edge.
+    pub fn cast_ray(&self, from: &Location, direction: &Direction, range: usize) -> RayHit {
+        assert!(self.topology().has(direction), "{:?} isn't a direction in a {} world", direction, self.topology().name());
+        let mut location = *from;
+        for distance in 1..=range {
+            let Some(next) = self.neighbour(&location, direction) else { return RayHit::Edge(distance - 1) };
+            if next.index() == from.index() { break }
+            if self.terrain_at(&next) == Terrain::Wall {
+                return RayHit::Wall(next, distance);
+            }
+            if let Some(id) = self.get_entity_id(&next) {
+                return RayHit::Entity(id, distance);
+            }
+            location = next;
+        }
+        RayHit::Nothing
+    }
+
+    /// The offsets along one axis that are within the radius of a coordinate, with each cell reached only once.
+    fn axis_offsets(&self, coordinate: usize, radius: usize, size: usize) -> RangeInclusive<isize> {
+        match self.boundary() {
+            Boundary::Wrap => -(radius.min((size - 1) / 2) as isize)..=radius.min(size / 2) as isize,
+            Boundary::Solid => -(radius.min(coordinate) as isize)..=radius.min(size - 1 - coordinate) as isize,
+        }
+    }
+
+    fn shifted(&self, location: &Location, dx: isize, dy: isize) -> Location {
+        let x = (location.x() as isize + dx).rem_euclid(self.width() as isize) as usize;
+        let y = (location.y() as isize + dy).rem_euclid(self.height() as isize) as usize;
+        Location::at(x, y, self)
+    }
+}
+
+fn distance_squared(dx: isize, dy: isize) -> usize {
+    dx.unsigned_abs().pow(2) + dy.unsigned_abs().pow(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::Entity;
+    use crate::rng_buffer;
+
+    /// A 10x8 world with entities at each corner and one in the middle, with ids 0 to 4 in that order.
+    fn corner_world(boundary: Boundary) -> World {
+        let _lock = rng_buffer::lock_for_test();
+        let mut world = World::new(10, 8);
+        world.set_boundary(boundary);
+        for (x, y) in [(0, 0), (9, 0), (0, 7), (9, 7), (5, 4)] {
+            world.place_entity(Entity::new(x, y, &world)).unwrap();
+        }
+        world
+    }
+
+    #[test]
+    fn queries_reach_across_wrapped_edges() {
+        let world = corner_world(Boundary::Wrap);
+        let origin = Location::at(0, 0, &world);
+        assert_eq!(world.displacement(&origin, &Location::at(9, 7, &world)), (-1, -1));
+        assert_eq!(world.entities_within(&origin, 1).collect::<Vec<_>>(), [2, 1, 0]);
+        assert_eq!(world.entities_in_rect(&Location::at(9, 7, &world), 2, 2).collect::<Vec<_>>(), [3, 2, 1, 0]);
+        assert_eq!(world.nearest_entities(&origin, 4), [0, 1, 2, 3]);
+        assert_eq!(world.count_neighbours(&origin, Neighbourhood::Moore), 3);
+        assert_eq!(world.count_neighbours(&origin, Neighbourhood::VonNeumann), 2);
+        assert!(matches!(world.cast_ray(&origin, &Direction::West, 5), RayHit::Entity(1, 1)));
+        assert!(matches!(world.cast_ray(&Location::at(5, 4, &world), &Direction::North, 20), RayHit::Nothing));
+    }
+
+    #[test]
+    fn queries_stop_at_solid_edges() {
+        let world = corner_world(Boundary::Solid);
+        let origin = Location::at(0, 0, &world);
+        assert_eq!(world.displacement(&origin, &Location::at(9, 7, &world)), (9, 7));
+        assert_eq!(world.entities_within(&origin, 1).collect::<Vec<_>>(), [0]);
+        assert_eq!(world.entities_in_rect(&Location::at(9, 7, &world), 2, 2).collect::<Vec<_>>(), [3]);
+        assert_eq!(world.nearest_entities(&origin, 4), [0, 4, 2, 1]);
+        assert_eq!(world.count_neighbours(&origin, Neighbourhood::Moore), 0);
+        assert!(matches!(world.cast_ray(&origin, &Direction::West, 5), RayHit::Edge(0)));
+    }
+
+    #[test]
+    fn hex_worlds_query_their_own_directions() {
+        let _lock = rng_buffer::lock_for_test();
+        let mut world = World::new(10, 8);
+        world.set_topology(Topology::Hex).unwrap();
+        world.place_entity(Entity::new(4, 3, &world)).unwrap();
+        world.place_entity(Entity::new(3, 5, &world)).unwrap();
+        let origin = Location::at(4, 4, &world);
+        assert_eq!(world.count_neighbours(&origin, Neighbourhood::Hex), 2);
+        assert!(matches!(world.cast_ray(&origin, &Direction::Northeast, 5), RayHit::Entity(0, 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't a direction in a hex world")]
+    fn rays_reject_directions_outside_the_topology() {
+        let mut world = World::new(10, 8);
+        world.set_topology(Topology::Hex).unwrap();
+        world.cast_ray(&Location::at(4, 4, &world), &Direction::North, 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "neighbourhood doesn't fit a hex world")]
+    fn neighbour_counts_reject_neighbourhoods_outside_the_topology() {
+        let mut world = World::new(10, 8);
+        world.set_topology(Topology::Hex).unwrap();
+        world.count_neighbours(&Location::at(4, 4, &world), Neighbourhood::Moore);
+    }
+
+    #[test]
+    fn tiny_wrapped_worlds_count_each_neighbour_once() {
+        let _lock = rng_buffer::lock_for_test();
+        let mut world = World::new(2, 2);
+        world.place_entity(Entity::new(1, 0, &world)).unwrap();
+        assert_eq!(world.count_neighbours(&Location::at(0, 0, &world), Neighbourhood::Moore), 1);
+        assert_eq!(world.entities_within(&Location::at(0, 0, &world), 5).count(), 1);
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Add;
+use crate::clusters::{self, SIZE_BUCKETS};
+
+/// Counts of what happened during a single tick.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TickStats {
+    pub tick: u64,
+    pub entities: usize,
+    pub moves: usize,
+    /// Moves that were blocked, whether by a conflict or an occupied cell.
+    pub blocked: usize,
+    pub turns: usize,
+    pub waits: usize,
+    /// Entities still busy with an earlier action, which didn't act.
+    pub busy: usize,
+    /// Cells that more than one entity tried to move into.
+    pub conflicts: usize,
+    pub total_energy: f64,
+    pub total_age: u64,
+    /// Groups of entities in touching cells, counting lone entities as clusters of one.
+    pub clusters: usize,
+    pub largest_cluster: usize,
+    /// The number of clusters in each range of sizes, as described for clusters::SIZE_BUCKETS.
+    pub cluster_sizes: [usize; SIZE_BUCKETS],
+}
+
+impl TickStats {
+    pub fn mean_energy(&self) -> f64 {
+        if self.entities == 0 { 0.0 } else { self.total_energy / self.entities as f64 }
+    }
+
+    pub fn mean_age(&self) -> f64 {
+        if self.entities == 0 { 0.0 } else { self.total_age as f64 / self.entities as f64 }
+    }
+
+    pub fn mean_cluster_size(&self) -> f64 {
+        if self.clusters == 0 { 0.0 } else { self.entities as f64 / self.clusters as f64 }
+    }
+}
+
+impl Add for TickStats {
+    type Output = TickStats;
+
+    fn add(self, other: TickStats) -> TickStats {
+        TickStats {
+            tick: self.tick.max(other.tick),
+            entities: self.entities + other.entities,
+            moves: self.moves + other.moves,
+            blocked: self.blocked + other.blocked,
+            turns: self.turns + other.turns,
+            waits: self.waits + other.waits,
+            busy: self.busy + other.busy,
+            conflicts: self.conflicts + other.conflicts,
+            total_energy: self.total_energy + other.total_energy,
+            total_age: self.total_age + other.total_age,
+            clusters: self.clusters + other.clusters,
+            largest_cluster: self.largest_cluster.max(other.largest_cluster),
+            cluster_sizes: std::array::from_fn(|i| self.cluster_sizes[i] + other.cluster_sizes[i]),
+        }
+    }
+}
+
+/// Streams TickStats to a CSV file, one row every interval ticks.
+pub struct StatsRecorder {
+    out: BufWriter<File>,
+    interval: u64,
+}
+
+impl StatsRecorder {
+    getter!(interval: u64);
+
+    pub fn create(path: &str, interval: u64) -> std::io::Result<StatsRecorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "tick,entities,moves,blocked,turns,waits,busy,conflicts,mean_energy,mean_age,clusters,largest_cluster,mean_cluster_size")?;
+        for bucket in 0..SIZE_BUCKETS {
+            write!(out, ",clusters_of_{}", clusters::bucket_name(bucket))?;
+        }
+        writeln!(out)?;
+        out.flush()?;
+        Ok(StatsRecorder { out, interval: interval.max(1) })
+    }
+
+    pub fn is_sampled(&self, tick: u64) -> bool {
+        tick % self.interval == 0
+    }
+
+    /// Writes a row and flushes, so the file is complete even if the run is cut short.
+    pub fn write(&mut self, stats: &TickStats) -> std::io::Result<()> {
+        write!(
+            self.out, "{},{},{},{},{},{},{},{},{:.6},{:.3},{},{},{:.3}",
+            stats.tick, stats.entities, stats.moves, stats.blocked, stats.turns, stats.waits, stats.busy,
+            stats.conflicts, stats.mean_energy(), stats.mean_age(),
+            stats.clusters, stats.largest_cluster, stats.mean_cluster_size(),
+        )?;
+        for count in &stats.cluster_sizes {
+            write!(self.out, ",{}", count)?;
+        }
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
@@ -0,0 +1,18 @@
+use crate::entity::Entity;
+use crate::rng_buffer;
+use crate::world::World;
+
+/// Scatters Wanderers uniformly across the world. Other behaviours are only spawned by scenarios.
+pub fn load(world: &mut World, entity_count: u32) {
+    let mut count = 0;
+    while count < entity_count {
+        let x = (rng_buffer::generate_next() * world.width() as f64) as usize;
+        let y = (rng_buffer::next() * world.height() as f64) as usize;
+        let mut entity = Entity::new(x, y, world);
+        // Lineage is the eighth of the world the entity started in, so that mixing is visible over time.
+        entity.lineage = (4 * x / world.width() + 4 * (2 * y / world.height())) as u32;
+        if world.place_entity(entity).is_ok() {
+            count += 1;
+        }
+    }
+}
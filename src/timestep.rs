@@ -0,0 +1,58 @@
+use std::time::{Duration, Instant};
+
+/// Schedules fixed-length simulation ticks and independently rate-limited frames.
+pub struct Timestep {
+    tick_duration: Duration,
+    frame_duration: Duration,
+    max_ticks_per_update: u32,
+    next_tick: Instant,
+    next_frame: Instant,
+}
+
+impl Timestep {
+    pub fn new(target_tps: u32, target_fps: u32) -> Timestep {
+        let tick_duration = Duration::from_secs(1) / target_tps.max(1);
+        let frame_duration = Duration::from_secs(1) / target_fps.max(1);
+        // Allow one extra tick per frame for catching up, beyond that the backlog is dropped.
+        let max_ticks_per_update = (frame_duration.as_nanos() / tick_duration.as_nanos()) as u32 + 1;
+        let now = Instant::now();
+        Timestep {
+            tick_duration,
+            frame_duration,
+            max_ticks_per_update,
+            next_tick: now + tick_duration,
+            next_frame: now + frame_duration,
+        }
+    }
+
+    /// The number of ticks that have fallen due by now, which are then considered taken.
+    /// If the simulation has fallen too far behind, the excess ticks are dropped rather than
+    /// letting the backlog grow without bound.
+    pub fn take_due_ticks(&mut self, now: Instant) -> u32 {
+        let mut ticks = 0;
+        while self.next_tick <= now {
+            if ticks == self.max_ticks_per_update {
+                self.next_tick = now + self.tick_duration;
+                break;
+            }
+            self.next_tick += self.tick_duration;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    /// Whether a frame has fallen due by now, in which case it is considered taken.
+    pub fn take_due_frame(&mut self, now: Instant) -> bool {
+        if self.next_frame > now { return false }
+        self.next_frame += self.frame_duration;
+        if self.next_frame <= now {
+            self.next_frame = now + self.frame_duration;
+        }
+        true
+    }
+
+    /// When the next tick or frame falls due, whichever is sooner.
+    pub fn next_wake(&self) -> Instant {
+        self.next_tick.min(self.next_frame)
+    }
+}
@@ -0,0 +1,222 @@
+//! The shape of the world's grid: which cells are next to each other, and how cells are laid out in the window.
+//! Every topology uses a subset of the compass directions, so that actions, outcomes and conflicts work the
+//! same way whatever the shape.
+
+use crate::{graphics_window, rng_buffer};
+use crate::graphics_window::Color;
+use crate::world::Direction;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// Square cells with eight neighbours, sharing an edge or a corner.
+    Moore,
+    /// Square cells with four neighbours, sharing an edge.
+    VonNeumann,
+    /// Hexagonal cells with six neighbours, in rows with every odd row shifted half a cell to the right.
+    /// Hex worlds must be an even number of rows high, so that rows still alternate across the top and bottom
+    /// edges when wrapping.
+    Hex,
+}
+
+const VON_NEUMANN_DIRECTIONS: [Direction; 4] = [Direction::North, Direction::East, Direction::South, Direction::West];
+const HEX_DIRECTIONS: [Direction; 6] = [
+    Direction::Northeast, Direction::East, Direction::Southeast,
+    Direction::Southwest, Direction::West, Direction::Northwest,
+];
+
+/// The width and height in pixels of a hex cell. Odd rows are shifted right by half of this.
+const HEX_CELL_SIZE: usize = 2;
+
+impl Topology {
+    pub const ALL: [Topology; 3] = [Topology::Moore, Topology::VonNeumann, Topology::Hex];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Topology::Moore => "moore",
+            Topology::VonNeumann => "von-neumann",
+            Topology::Hex => "hex",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Topology> {
+        Self::ALL.into_iter().find(|topology| topology.name() == name)
+    }
+
+    pub fn index(&self) -> usize {
+        Self::ALL.iter().position(|topology| topology == self).unwrap()
+    }
+
+    pub fn from_index(index: usize) -> Option<Topology> {
+        Self::ALL.get(index).copied()
+    }
+
+    /// The directions to a cell's neighbours, clockwise from North.
+    pub fn directions(&self) -> &'static [Direction] {
+        match self {
+            Topology::Moore => &Direction::ALL,
+            Topology::VonNeumann => &VON_NEUMANN_DIRECTIONS,
+            Topology::Hex => &HEX_DIRECTIONS,
+        }
+    }
+
+    pub fn has(&self, direction: &Direction) -> bool {
+        self.offset(direction, 0).is_some()
+    }
+
+    /// Whether a step in the direction is to a neighbour sharing only a corner, and so further away.
+    pub fn is_diagonal(&self, direction: &Direction) -> bool {
+        *self == Topology::Moore && direction.x() != 0 && direction.y() != 0
+    }
+
+    /// The change in x and y of a step in the direction from a cell in row y, or None if the direction isn't
+    /// one of this topology's.
+    pub fn offset(&self, direction: &Direction, y: usize) -> Option<(isize, isize)> {
+        match self {
+            Topology::Moore => Some((direction.x(), direction.y())),
+            Topology::VonNeumann => (direction.x() == 0 || direction.y() == 0).then_some((direction.x(), direction.y())),
+            Topology::Hex => match (direction.x(), direction.y()) {
+                (_, 0) => Some((direction.x(), 0)),
+                (0, _) => None,
+                // Diagonal steps go to the row above or below, which is shifted half a cell one way or the other.
+                (dx, dy) if y % 2 == 0 => Some((dx.min(0), dy)),
+                (dx, dy) => Some((dx.max(0), dy)),
+            },
+        }
+    }
+
+    pub fn random_direction(&self) -> Direction {
+        match self {
+            Topology::Moore => Direction::random(),
+            _ => {
+                let directions = self.directions();
+                directions[(rng_buffer::next() * directions.len() as f64) as usize % directions.len()]
+            }
+        }
+    }
+
+    /// Whether a world of the given height can have this topology.
+    pub fn fits_height(&self, height: usize) -> bool {
+        *self != Topology::Hex || height % 2 == 0
+    }
+
+    /// The width and height in pixels of a world of the given size.
+    pub fn pixel_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Topology::Moore | Topology::VonNeumann => (width, height),
+            Topology::Hex => (HEX_CELL_SIZE * width + HEX_CELL_SIZE / 2, HEX_CELL_SIZE * height),
+        }
+    }
+
+    /// The number of columns and rows of cells that are at least partly within the given number of pixels.
+    pub fn cells_within(&self, pixel_width: usize, pixel_height: usize) -> (usize, usize) {
+        match self {
+            Topology::Moore | Topology::VonNeumann => (pixel_width, pixel_height),
+            Topology::Hex => (pixel_width.div_ceil(HEX_CELL_SIZE), pixel_height.div_ceil(HEX_CELL_SIZE)),
+        }
+    }
+
+    /// The left, top, width and height in pixels of the rectangle the cell at (x, y) is drawn as.
+    pub fn cell_rect(&self, x: usize, y: usize) -> (usize, usize, usize, usize) {
+        match self {
+            Topology::Moore | Topology::VonNeumann => (x, y, 1, 1),
+            Topology::Hex => (
+                HEX_CELL_SIZE * x + (y % 2) * HEX_CELL_SIZE / 2,
+                HEX_CELL_SIZE * y,
+                HEX_CELL_SIZE,
+                HEX_CELL_SIZE,
+            ),
+        }
+    }
+
+    /// The pixel at the middle of the cell at (x, y).
+    pub fn cell_center(&self, x: usize, y: usize) -> (usize, usize) {
+        let (left, top, width, height) = self.cell_rect(x, y);
+        (left + width / 2, top + height / 2)
+    }
+
+    /// The cell drawn at the pixel, if any, in a world of the given size.
+    pub fn cell_at_pixel(&self, pixel_x: usize, pixel_y: usize, width: usize, height: usize) -> Option<(usize, usize)> {
+        let (x, y) = match self {
+            Topology::Moore | Topology::VonNeumann => (pixel_x, pixel_y),
+            Topology::Hex => {
+                let y = pixel_y / HEX_CELL_SIZE;
+                (pixel_x.checked_sub((y % 2) * HEX_CELL_SIZE / 2)? / HEX_CELL_SIZE, y)
+            }
+        };
+        (x < width && y < height).then_some((x, y))
+    }
+
+    pub fn draw_cell(&self, x: usize, y: usize, color: Color) {
+        match self {
+            Topology::Moore | Topology::VonNeumann => graphics_window::draw_pixel(x, y, color),
+            Topology::Hex => {
+                let (left, top, width, height) = self.cell_rect(x, y);
+                graphics_window::fill_rect(left, top, width, height, color);
+            }
+        }
+    }
+
+    /// Mixes the colour into the cell's pixels, clipped to the window, as for graphics_window::blend_pixel.
+    pub fn blend_cell(&self, x: usize, y: usize, color: Color, opacity: f32) {
+        let (left, top, width, height) = self.cell_rect(x, y);
+        for pixel_y in top..(top + height).min(graphics_window::height()) {
+            for pixel_x in left..(left + width).min(graphics_window::width()) {
+                graphics_window::blend_pixel(pixel_x, pixel_y, color, opacity);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{Location, World};
+
+    #[test]
+    fn steps_back_return_to_the_start() {
+        for topology in Topology::ALL {
+            let mut world = World::new(5, 4);
+            world.set_topology(topology).unwrap();
+            for y in 0..4 {
+                for x in 0..5 {
+                    let start = Location::at(x, y, &world);
+                    let mut neighbours: Vec<usize> = topology.directions().iter()
+                        .map(|direction| {
+                            let next = world.neighbour(&start, direction).unwrap();
+                            let back = world.neighbour(&next, &direction.opposite()).unwrap();
+                            assert_eq!(back.index(), start.index(), "{:?} {:?} from ({}, {})", topology, direction, x, y);
+                            next.index()
+                        })
+                        .collect();
+                    neighbours.sort_unstable();
+                    neighbours.dedup();
+                    assert_eq!(neighbours.len(), topology.directions().len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hex_rows_alternate_their_diagonals() {
+        let hex = Topology::Hex;
+        assert!(!hex.has(&Direction::North));
+        assert_eq!(hex.offset(&Direction::Northeast, 0), Some((0, -1)));
+        assert_eq!(hex.offset(&Direction::Northeast, 1), Some((1, -1)));
+        assert_eq!(hex.offset(&Direction::Southwest, 0), Some((-1, 1)));
+        assert_eq!(hex.offset(&Direction::Southwest, 1), Some((0, 1)));
+        assert!(!hex.fits_height(5));
+        assert!(Topology::Moore.is_diagonal(&Direction::Northeast));
+        assert!(!hex.is_diagonal(&Direction::Northeast));
+    }
+
+    #[test]
+    fn cell_centers_map_back_to_their_cells() {
+        for topology in Topology::ALL {
+            for (x, y) in [(0, 0), (3, 1), (6, 4), (7, 5)] {
+                let (pixel_x, pixel_y) = topology.cell_center(x, y);
+                assert_eq!(topology.cell_at_pixel(pixel_x, pixel_y, 8, 6), Some((x, y)), "{:?}", topology);
+            }
+        }
+        assert_eq!(Topology::Hex.cell_at_pixel(0, HEX_CELL_SIZE, 8, 6), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! The order entities act in within a tick, which changes what each of them sees of the others' moves.
+
+use crate::world::{Boundary, Location, World};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpdateScheme {
+    /// Every entity chooses its action from the world as it was at the start of the tick, and the outcomes are
+    /// applied together, with moves into the same cell blocking each other.
+    Synchronous,
+    /// Entities act one at a time in a new random order each tick, each seeing the moves of those before it.
+    RandomSequential,
+    /// Cells are split into classes like the squares of a checkerboard, far enough apart that entities in the
+    /// same class can't reach the same cell. The classes act one after another, each seeing the moves of those
+    /// before it, and the entities within a class act in parallel.
+    Checkerboard,
+}
+
+/// Entities reach at most one cell away, so cells three apart can't be reached by the same entities.
+const CHECKERBOARD_SPACING: usize = 3;
+
+impl UpdateScheme {
+    pub const ALL: [UpdateScheme; 3] = [UpdateScheme::Synchronous, UpdateScheme::RandomSequential, UpdateScheme::Checkerboard];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpdateScheme::Synchronous => "synchronous",
+            UpdateScheme::RandomSequential => "random-sequential",
+            UpdateScheme::Checkerboard => "checkerboard",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<UpdateScheme> {
+        Self::ALL.into_iter().find(|scheme| scheme.name() == name)
+    }
+
+    pub fn next(&self) -> UpdateScheme {
+        let index = Self::ALL.iter().position(|scheme| scheme == self).unwrap();
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+/// The number of checkerboard classes in the world, which are numbered from 0.
+pub fn checkerboard_class_count(world: &World) -> usize {
+    axis_class_count(world.width(), world.boundary()) * axis_class_count(world.height(), world.boundary())
+}
+
+/// The checkerboard class of the cell at the location.
+pub fn checkerboard_class(world: &World, location: &Location) -> usize {
+    let columns = axis_class_count(world.width(), world.boundary());
+    axis_class(location.y(), world.height(), world.boundary()) * columns
+        + axis_class(location.x(), world.width(), world.boundary())
+}
+
+/// Where the world wraps and its size isn't a multiple of the spacing, the columns or rows left over at the end
+/// are next to the first ones, so each of them gets a class of its own.
+fn axis_class_count(size: usize, boundary: Boundary) -> usize {
+    match boundary {
+        Boundary::Wrap => CHECKERBOARD_SPACING + size % CHECKERBOARD_SPACING,
+        Boundary::Solid => CHECKERBOARD_SPACING,
+    }
+}
+
+fn axis_class(coordinate: usize, size: usize, boundary: Boundary) -> usize {
+    let whole = size - size % CHECKERBOARD_SPACING;
+    match boundary {
+        Boundary::Wrap if coordinate >= whole => CHECKERBOARD_SPACING + coordinate - whole,
+        _ => coordinate % CHECKERBOARD_SPACING,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cells in the same class must be far enough apart that no cell is a neighbour of both,
+    /// measuring the short way round in wrapping worlds.
+    fn assert_classes_apart(world: &World) {
+        let count = checkerboard_class_count(world);
+        let locations: Vec<Location> = (0..world.height())
+            .flat_map(|y| (0..world.width()).map(move |x| (x, y)))
+            .map(|(x, y)| Location::at(x, y, world))
+            .collect();
+        for a in &locations {
+            let class = checkerboard_class(world, a);
+            assert!(class < count);
+            for b in locations.iter().filter(|b| b.index() != a.index()) {
+                if checkerboard_class(world, b) != class { continue }
+                let (dx, dy) = world.displacement(a, b);
+                assert!(dx.abs() >= 3 || dy.abs() >= 3,
+                    "({}, {}) and ({}, {}) share class {} in a {}x{} {:?} world",
+                    a.x(), a.y(), b.x(), b.y(), class, world.width(), world.height(), world.boundary());
+            }
+        }
+    }
+
+    #[test]
+    fn checkerboard_classes_are_apart_for_any_size() {
+        for boundary in [Boundary::Wrap, Boundary::Solid] {
+            for width in 1..=11 {
+                for height in [1, 2, 4, 5, 7, 9] {
+                    let mut world = World::new(width, height);
+                    world.set_boundary(boundary);
+                    assert_classes_apart(&world);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapped_leftover_columns_get_their_own_classes() {
+        let world = World::new(8, 6);
+        assert_eq!(checkerboard_class_count(&world), 5 * 3);
+        let classes: Vec<usize> = (0..8).map(|x| checkerboard_class(&world, &Location::at(x, 0, &world))).collect();
+        assert_eq!(classes, [0, 1, 2, 0, 1, 2, 3, 4]);
+    }
+}
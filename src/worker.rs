@@ -0,0 +1,65 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+/// A thread kept alive to run the same job whenever asked, rather than spawning a thread for each run.
+pub struct Worker {
+    requests: Option<Sender<()>>,
+    finished: Receiver<(Instant, Instant)>,
+    thread: Option<JoinHandle<()>>,
+    running: bool,
+}
+
+impl Worker {
+    pub fn spawn(name: &str, job: fn()) -> Worker {
+        let (requests, requested) = channel::<()>();
+        let (finish, finished) = channel();
+        let thread = std::thread::Builder::new()
+            .name(String::from(name))
+            .spawn(move || {
+                // Stops once the Worker is dropped, as that closes the channel.
+                while requested.recv().is_ok() {
+                    let start = Instant::now();
+                    job();
+                    if finish.send((start, Instant::now())).is_err() { break }
+                }
+            })
+            .expect("should be able to spawn worker thread");
+        Worker {
+            requests: Some(requests),
+            finished,
+            thread: Some(thread),
+            running: false,
+        }
+    }
+
+    /// Starts the job on the worker, which must not already be running it.
+    pub fn start(&mut self) {
+        assert!(!self.running, "worker should not already be running");
+        self.requests.as_ref().unwrap().send(()).expect("worker thread should be alive");
+        self.running = true;
+    }
+
+    /// Waits for the started job to finish, returning when it started and ended.
+    pub fn join(&mut self) -> (Instant, Instant) {
+        assert!(self.running, "worker should be running");
+        self.running = false;
+        self.finished.recv().expect("worker thread should be alive")
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if self.running {
+            self.join();
+        }
+        self.requests.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,100 +1,390 @@
 use rayon::prelude::*;
-use rayon::slice::Iter;
-use crate::entity::Entity;
-use crate::rng_buffer;
+use crate::action::{ActionDurations, Outcome};
+use crate::chunked_grid::ChunkedGrid;
+use crate::entity::{Entity, EntityMut, EntityRef};
+use crate::entity_storage::{EntityLayout, EntityStorage};
+use crate::topology::Topology;
+use crate::{execution, rng_buffer};
 
-type EntityId = usize;
+pub type EntityId = usize;
 
+#[derive(Clone)]
 pub struct World {
     width: usize,
     height: usize,
-    entity_grid: Vec<Option<EntityId>>,
-    entities: Vec<Entity>,
+    boundary: Boundary,
+    topology: Topology,
+    action_durations: ActionDurations,
+    terrain: ChunkedGrid<Terrain>,
+    wall_count: usize,
+    entity_grid: ChunkedGrid<GridEntry>,
+    /// The number of entities in each chunk of entity_grid, so that empty chunks can be skipped.
+    chunk_populations: Vec<u32>,
+    entities: EntityStorage,
+}
+
+/// The id of the entity in a cell of the entity grid, if any, packed into four bytes to keep the grid small.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct GridEntry(u32);
+
+impl GridEntry {
+    const NONE: GridEntry = GridEntry(u32::MAX);
+
+    fn of(id: EntityId) -> GridEntry {
+        GridEntry(u32::try_from(id).ok().filter(|id| *id != u32::MAX).expect("entity id should fit in the entity grid"))
+    }
+
+    fn id(&self) -> Option<EntityId> {
+        (*self != Self::NONE).then_some(self.0 as EntityId)
+    }
+}
+
+impl Default for GridEntry {
+    fn default() -> GridEntry {
+        GridEntry::NONE
+    }
+}
+
+/// What happens to entities moving off an edge of the world.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Boundary {
+    /// They come back on at the opposite edge.
+    Wrap,
+    /// They are blocked, as if by a wall.
+    Solid,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Terrain {
+    #[default]
+    Open,
+    /// Blocks movement, and can't have an entity placed on it.
+    Wall,
+}
+
+impl Terrain {
+    /// How many times as costly moving onto this terrain is as moving onto open ground, or None if it can't be moved onto.
+    pub fn move_cost(&self) -> Option<u32> {
+        match self {
+            Terrain::Open => Some(1),
+            Terrain::Wall => None,
+        }
+    }
 }
 
 impl World {
     getter!(width: usize);
     getter!(height: usize);
-
-    fn new_entity_grid(width: usize, height: usize) -> Vec<Option<EntityId>> {
-        let mut entity_grid = Vec::with_capacity(width * height);
-        entity_grid.resize_with(width * height, || None);
-        return entity_grid;
-    }
+    getter!(boundary: Boundary);
+    getter!(topology: Topology);
+    getter!(action_durations: ActionDurations);
+    getter!(wall_count: usize);
 
     pub fn new(width: usize, height: usize) -> World {
+        let entity_grid = ChunkedGrid::new(width, height);
         World {
-            entity_grid: Self::new_entity_grid(width, height),
-            entities: Vec::with_capacity(width * height),
+            chunk_populations: vec![0; entity_grid.chunk_count()],
+            entity_grid,
+            entities: EntityStorage::new(EntityLayout::Aos),
+            boundary: Boundary::Wrap,
+            topology: Topology::Moore,
+            action_durations: ActionDurations::default(),
+            terrain: ChunkedGrid::new(width, height),
+            wall_count: 0,
             width,
             height,
         }
     }
 
-    pub fn add(&self, location: &Location, direction: &Direction) -> Location {
-        let x = location.x() + self.width.checked_add_signed(direction.x()).expect("adding +1/-1 to width should not overflow");
-        let y = location.y() + self.height.checked_add_signed(direction.y()).expect("adding +1/-1 to height should not overflow");
-        Location::at(x, y, &self)
+    pub fn set_boundary(&mut self, boundary: Boundary) {
+        self.boundary = boundary;
     }
 
-    pub fn get_entity(&self, location: &Location) -> Option<&Entity> {
-        let entity = self.entity_grid.get(location.index()).unwrap();
-        match entity {
-            None => None,
-            Some(id) => Some(&self.entities[*id]),
+    /// Fails if the world's height doesn't suit the topology.
+    pub fn set_topology(&mut self, topology: Topology) -> Result<(), String> {
+        if !topology.fits_height(self.height) {
+            return Err(format!(
+                "a {} world can't be {} high, as it must have an even number of rows to wrap",
+                topology.name(), self.height,
+            ));
         }
+        self.topology = topology;
+        Ok(())
+    }
+
+    pub fn set_action_durations(&mut self, durations: ActionDurations) {
+        self.action_durations = durations;
+    }
+
+    pub fn entity_layout(&self) -> EntityLayout {
+        self.entities.layout()
+    }
+
+    /// Moves the entities into the given layout, keeping their ids.
+    pub fn set_entity_layout(&mut self, layout: EntityLayout) {
+        let entities = std::mem::replace(&mut self.entities, EntityStorage::new(layout));
+        self.entities = entities.into_layout(layout);
+    }
+
+    pub fn terrain_at(&self, location: &Location) -> Terrain {
+        *self.terrain.get(location.x, location.y)
     }
 
-    pub fn get_entity_mut(&mut self, location: &Location) -> Option<&mut Entity> {
-        let entity = self.entity_grid.get(location.index()).unwrap();
-        match entity {
-            None => None,
-            Some(id) => Some(&mut self.entities[*id]),
+    /// Fails if making the location a wall would put it under an entity.
+    pub fn set_terrain(&mut self, location: &Location, terrain: Terrain) -> Result<(), String> {
+        if let Some(id) = self.get_entity_id(location).filter(|_| terrain == Terrain::Wall) {
+            return Err(format!("can't put a wall under entity {} at ({}, {})", id, location.x(), location.y()));
+        }
+        if self.terrain_at(location) == terrain {
+            return Ok(());
+        }
+        let cell = self.terrain.get_mut(location.x, location.y);
+        match (*cell, terrain) {
+            (Terrain::Open, Terrain::Wall) => self.wall_count += 1,
+            (Terrain::Wall, Terrain::Open) => self.wall_count -= 1,
+            _ => {}
         }
+        *cell = terrain;
+        Ok(())
     }
 
-    pub fn iter_entities_par(&self) -> Iter<Entity> {
-        self.entities.par_iter()
+    /// The locations of all walls, chunk by chunk.
+    pub fn walls(&self) -> Vec<Location> {
+        let walls_by_chunk = execution::map(&self.terrain.allocated_chunks(), |(chunk, cells)| {
+            cells.iter().enumerate()
+                .filter(|(_, terrain)| **terrain == Terrain::Wall)
+                .map(|(cell, _)| {
+                    let (x, y) = self.terrain.position(*chunk, cell);
+                    Location::at(x, y, self)
+                })
+                .collect::<Vec<_>>()
+        });
+        walls_by_chunk.concat()
     }
 
-    pub fn move_entity(&mut self, location: &Location, direction: &Direction) -> Result<(), ()> {
-        let new_location = self.add(location, direction);
+    /// The indices of the chunks that have entities in them. Chunks are CHUNK_SIZE cells square,
+    /// numbered across and then down the world.
+    pub fn populated_chunks(&self) -> Vec<usize> {
+        self.chunk_populations.iter().enumerate()
+            .filter(|(_, population)| **population > 0)
+            .map(|(chunk, _)| chunk)
+            .collect()
+    }
 
-        let source = self.entity_grid.get_mut(location.index()).unwrap().take();
-        let target = self.entity_grid.get_mut(new_location.index()).unwrap();
+    /// The index of the chunk holding the location.
+    pub fn chunk_of(&self, location: &Location) -> usize {
+        self.entity_grid.chunk_of(location.x, location.y)
+    }
 
-        if source.is_none() || target.is_some() {
-            return Err(())
+    /// Whether a move from the location could leave its chunk.
+    pub fn is_on_chunk_edge(&self, location: &Location) -> bool {
+        self.entity_grid.is_on_chunk_edge(location.x, location.y)
+    }
+
+    /// The location an entity would move to in the given direction, if the boundary and terrain allow it.
+    /// Whether another entity is there is not checked.
+    #[inline]
+    pub fn target(&self, location: &Location, direction: &Direction) -> Option<Location> {
+        let target = self.neighbour(location, direction)?;
+        (self.terrain_at(&target) == Terrain::Open).then_some(target)
+    }
+
+    /// The location one step in the given direction, unless a solid boundary is in the way or the direction
+    /// isn't one of the topology's.
+    pub fn neighbour(&self, location: &Location, direction: &Direction) -> Option<Location> {
+        let (dx, dy) = self.topology.offset(direction, location.y())?;
+        if self.boundary == Boundary::Solid {
+            location.x().checked_add_signed(dx).filter(|x| *x < self.width)?;
+            location.y().checked_add_signed(dy).filter(|y| *y < self.height)?;
         }
+        Some(self.add(location, direction))
+    }
 
-        let id = source.unwrap();
-        self.entities.get_mut(id).unwrap().location = new_location.clone();
-        target.replace(id);
-        return Ok(());
+    /// The location one step in the given direction, which must be one of the topology's, wrapping around the edges.
+    pub fn add(&self, location: &Location, direction: &Direction) -> Location {
+        offset(location, direction, self.topology, self.width, self.height)
     }
 
-    pub fn place_entity(&mut self, entity: Entity) -> Result<(), ()> {
-        let index = entity.location.index();
-        if self.entity_grid.get(index).unwrap().is_some() {
-            return Err(());
+    /// The directions to a location's neighbours.
+    pub fn directions(&self) -> &'static [Direction] {
+        self.topology.directions()
+    }
+
+    pub fn get_entity(&self, location: &Location) -> Option<EntityRef<'_>> {
+        self.entities.get(self.get_entity_id(location)?)
+    }
+
+    pub fn get_entity_mut(&mut self, location: &Location) -> Option<EntityMut<'_>> {
+        self.entities.get_mut(self.get_entity_id(location)?)
+    }
+
+    pub fn get_entity_id(&self, location: &Location) -> Option<EntityId> {
+        self.entity_grid.get(location.x, location.y).id()
+    }
+
+    pub fn entity(&self, id: EntityId) -> Option<EntityRef<'_>> {
+        self.entities.get(id)
+    }
+
+    pub fn entity_mut(&mut self, id: EntityId) -> Option<EntityMut<'_>> {
+        self.entities.get_mut(id)
+    }
+
+    /// All entities, in order of their ids, in whichever layout they are stored.
+    pub fn entities(&self) -> &EntityStorage {
+        &self.entities
+    }
+
+    pub fn entity_count(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Applies an outcome to every entity, with the entities updated in parallel unless execution is sequential.
+    ///
+    /// # Safety
+    /// Every move must be into a location that was unoccupied before any outcome was applied, and no two
+    /// moves may be into the same location, so that each cell of the entity grid is written by at most one entity.
+    pub unsafe fn apply_outcomes(&mut self, outcome_of: impl Fn(EntityId) -> Outcome + Sync + Send) {
+        let (topology, width, height, durations) = (self.topology, self.width, self.height, self.action_durations);
+
+        // Moves between chunks are few, so they are counted up front, which also allocates chunks being moved into.
+        let crossing = |id, location: &Location| match outcome_of(id) {
+            Outcome::Move(direction) => {
+                let from = self.chunk_of(location);
+                let to = self.chunk_of(&offset(location, &direction, topology, width, height));
+                (from != to).then_some((from, to))
+            }
+            _ => None,
+        };
+        let crossings: Vec<(usize, usize)> = match &self.entities {
+            EntityStorage::Aos(entities) => execution::map_indexed(entities, |id, entity| crossing(id, &entity.location)),
+            EntityStorage::Soa(columns) => execution::map_indexed(columns.locations(), crossing),
+        }.into_iter().flatten().collect();
+        for &(from, to) in &crossings {
+            self.entity_grid.allocate(to);
+            self.chunk_populations[from] -= 1;
+            self.chunk_populations[to] += 1;
+        }
+
+        let grid = self.entity_grid.disjoint_writer();
+        let apply = |id, mut entity: EntityMut| {
+            let outcome = outcome_of(id);
+            entity.apply_outcome(&outcome, &durations);
+            if let Outcome::Move(direction) = outcome {
+                let from = *entity.location;
+                let to = offset(&from, &direction, topology, width, height);
+                // Safety: The source is only written by the entity there, and the target only by the one entity moving into it.
+                unsafe {
+                    grid.write(from.x, from.y, GridEntry::NONE);
+                    grid.write(to.x, to.y, GridEntry::of(id));
+                }
+                *entity.location = to;
+            }
+        };
+        match &mut self.entities {
+            EntityStorage::Aos(entities) => execution::for_each_indexed_mut(entities, |id, entity| apply(id, entity.as_mut())),
+            EntityStorage::Soa(columns) if execution::is_sequential() => {
+                columns.iter_mut().enumerate().for_each(|(id, entity)| apply(id, entity));
+            }
+            EntityStorage::Soa(columns) => execution::install(|| {
+                columns.par_iter_mut().enumerate().for_each(|(id, entity)| apply(id, entity));
+            }),
+        }
+
+        // Chunks that every entity has left are freed, so that only populated chunks take memory.
+        for (from, _) in crossings {
+            if self.chunk_populations[from] == 0 {
+                self.entity_grid.free(from);
+            }
+        }
+    }
+
+    /// Applies an outcome to one entity, as apply_outcomes does for all of them, for entities that act one at a time.
+    /// Fails if the entity doesn't exist or moves into an occupied cell.
+    pub fn apply_outcome(&mut self, id: EntityId, outcome: &Outcome) -> Result<(), String> {
+        let durations = self.action_durations;
+        let missing = || format!("there is no entity {}", id);
+        if let Outcome::Move(direction) = outcome {
+            let from = self.entity(id).ok_or_else(missing)?.location();
+            self.move_entity(&from, direction)?;
+        }
+        self.entity_mut(id).ok_or_else(missing)?.apply_outcome(outcome, &durations);
+        Ok(())
+    }
+
+    pub fn move_entity(&mut self, location: &Location, direction: &Direction) -> Result<(), String> {
+        let new_location = self.add(location, direction);
+
+        let Some(id) = self.get_entity_id(location) else {
+            return Err(format!("there is no entity at ({}, {})", location.x(), location.y()))
+        };
+        if let Some(other) = self.get_entity_id(&new_location) {
+            return Err(format!("entity {} at ({}, {}) is in the way", other, new_location.x(), new_location.y()))
+        }
+
+        *self.entity_grid.get_mut(location.x, location.y) = GridEntry::NONE;
+        *self.entity_grid.get_mut(new_location.x, new_location.y) = GridEntry::of(id);
+        let (from, to) = (self.chunk_of(location), self.chunk_of(&new_location));
+        self.chunk_populations[from] -= 1;
+        self.chunk_populations[to] += 1;
+        if self.chunk_populations[from] == 0 {
+            self.entity_grid.free(from);
+        }
+        *self.entities.get_mut(id).unwrap().location = new_location;
+        Ok(())
+    }
+
+    pub fn place_entity(&mut self, entity: Entity) -> Result<(), String> {
+        let location = entity.location;
+        if let Some(other) = self.get_entity_id(&location) {
+            return Err(format!("entity {} is already at ({}, {})", other, location.x(), location.y()));
+        }
+        if self.terrain_at(&location) == Terrain::Wall {
+            return Err(format!("({}, {}) is a wall", location.x(), location.y()));
         }
 
         let id = self.entities.len() as EntityId;
         self.entities.push(entity);
 
-        self.entity_grid.get_mut(index)
-            .unwrap()
-            .replace(id);
-        return Ok(());
+        *self.entity_grid.get_mut(location.x, location.y) = GridEntry::of(id);
+        let chunk = self.chunk_of(&location);
+        self.chunk_populations[chunk] += 1;
+        Ok(())
     }
 }
 
+/// The location one step in the given direction, wrapping around the edges of a world of the given size.
+fn offset(location: &Location, direction: &Direction, topology: Topology, width: usize, height: usize) -> Location {
+    let (dx, dy) = topology.offset(direction, location.y()).expect("direction should be one of the world's topology");
+    let x = location.x() + width.checked_add_signed(dx).expect("adding +1/-1 to width should not overflow");
+    let y = location.y() + height.checked_add_signed(dy).expect("adding +1/-1 to height should not overflow");
+    Location::wrapped(x, y, width, height)
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Direction {
     North, Northeast, East, Southeast, South, Southwest, West, Northwest
 }
 
 impl Direction {
+    /// All directions, clockwise from North, so that ALL[d.index()] == d.
+    pub const ALL: [Direction; 8] = [
+        Direction::North, Direction::Northeast, Direction::East, Direction::Southeast,
+        Direction::South, Direction::Southwest, Direction::West, Direction::Northwest,
+    ];
+
+    pub fn from_index(index: usize) -> Option<Direction> {
+        Self::ALL.get(index).copied()
+    }
+
+    pub fn opposite(&self) -> Direction {
+        Self::ALL[(self.index() + 4) % 8]
+    }
+
+    pub fn is_diagonal(&self) -> bool {
+        self.x() != 0 && self.y() != 0
+    }
+
     pub fn x(&self) -> isize {
         match self {
             Direction::East | Direction::Northeast | Direction::Southeast => 1,
@@ -111,6 +401,20 @@ impl Direction {
         }
     }
 
+    /// Position of this direction on the compass, clockwise from North.
+    pub fn index(&self) -> usize {
+        match self {
+            Direction::North => 0,
+            Direction::Northeast => 1,
+            Direction::East => 2,
+            Direction::Southeast => 3,
+            Direction::South => 4,
+            Direction::Southwest => 5,
+            Direction::West => 6,
+            Direction::Northwest => 7,
+        }
+    }
+
     pub fn random() -> Direction {
         match (rng_buffer::next() * 8.0) as usize {
             0 => Direction::North,
@@ -139,9 +443,13 @@ impl Location {
     getter!(index: usize);
 
     pub fn at(x: usize, y: usize, world: &World) -> Location {
-        let x = x % world.width();
-        let y = y % world.height();
-        let index = x + world.width() * y;
+        Self::wrapped(x, y, world.width(), world.height())
+    }
+
+    fn wrapped(x: usize, y: usize, width: usize, height: usize) -> Location {
+        let x = x % width;
+        let y = y % height;
+        let index = x + width * y;
         Location { x, y, index }
     }
 }
@@ -1,294 +1,1218 @@
-use rayon::prelude::*;
-use crate::graphics_window;
+use std::mem::size_of;
+use std::ptr::{addr_of, addr_of_mut};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Instant;
+use crate::{execution, graphics_window};
 use crate::graphics_window::Color;
-use crate::world::{Direction, Location, World};
+use crate::world::{Direction, EntityId, Location, World};
 use crate::action::{Action, Outcome};
+use crate::color_mode::ColorMode;
+use crate::fps_counter::FrameStats;
+use crate::heatmap::{Heatmap, HeatmapKind, OverlayMode};
+use crate::profiler::{Phase, Profiler};
+use crate::stats::{StatsRecorder, TickStats};
+use crate::event_log::{Event, EventLogWriter};
+use crate::entity::Entity;
+use crate::history::{History, TickDelta};
+use crate::execution::Execution;
+use crate::worker::Worker;
+use crate::chunked_grid::ChunkedGrid;
+use crate::entity_storage::EntityStorage;
+use crate::clusters::Clusters;
+use crate::topology::Topology;
+use crate::update_scheme::{self, UpdateScheme};
+use crate::rng_buffer;
+
+/// Fraction of each heatmap value kept from one tick to the next.
+const HEATMAP_DECAY: f32 = 0.99;
+/// How many of the most recent ticks phase timings are kept for.
+const PROFILE_WINDOW: usize = 256;
+const WALL_COLOR: Color = Color::new(96, 96, 96);
+/// How many cells per entity there must be before clean_up clears conflicts entity by entity rather than
+/// clearing the whole grid, as finding an entity's targets costs far more than clearing one cell in order.
+const SPARSE_CLEAN_RATIO: usize = 64;
 
 static mut WORLD: Option<World> = None;
+// LOCATIONS, ACTIONS and OUTCOMES are indexed by entity id, so they only take space for occupied cells.
 static mut LOCATIONS: Vec<Location> = Vec::new();
-static mut ACTION_GRID: Vec<Option<Action>> = Vec::new();
-static mut CONFLICT_GRID: Vec<Conflict> = Vec::new();
-static mut OUTCOME_GRID: Vec<Option<Outcome>> = Vec::new();
+static mut ACTIONS: Vec<Action> = Vec::new();
+/// The chunks of the world that entities are in or are trying to move into this tick, in index order.
+/// These are the only chunks of CONFLICT_GRID and HEATMAP written to, so the rest needn't be allocated.
+static mut ACTIVE_CHUNKS: Vec<usize> = Vec::new();
+/// Written from many tasks at once, as several movers can target the same cell, so its cells are atomic.
+/// Relaxed ordering is enough, as bits are only set during determine_actions, and the end of that parallel
+/// loop orders them before anything reads them.
+static mut CONFLICT_GRID: Option<ChunkedGrid<Conflict>> = None;
+/// None until an entity's outcome is known.
+static mut OUTCOMES: Vec<Option<Outcome>> = Vec::new();
+/// The ids of the entities in the order they acted this tick, or empty if they acted together.
+static mut ORDER: Vec<EntityId> = Vec::new();
+static mut UPDATE_SCHEME: UpdateScheme = UpdateScheme::Synchronous;
+static mut HEATMAP: Option<Heatmap> = None;
 static mut DRAWING_ENABLED: bool = true;
+static mut HEATMAP_ENABLED: bool = true;
+static mut COLOR_MODE: ColorMode = ColorMode::Plain;
+static mut TICK: u64 = 0;
+static mut PROFILER: Option<Profiler> = None;
+static mut STATS: Option<StatsRecorder> = None;
+static mut EVENT_LOG: Option<EventLogWriter> = None;
+static mut HISTORY: Option<History> = None;
+static mut RENDER_WORKER: Option<Worker> = None;
+
+/// Creates functions giving references to a static through a raw pointer, so that the static itself is never
+/// borrowed. Callers must keep to the same rules as if they used the static directly.
+macro_rules! static_access {
+    ($var:ident: $typ:ty, $name:ident, $name_mut:ident) => {
+        unsafe fn $name() -> &'static $typ { &*addr_of!($var) }
+        static_access!($var: $typ, $name_mut);
+    };
+    ($var:ident: $typ:ty, $name_mut:ident) => {
+        unsafe fn $name_mut() -> &'static mut $typ { &mut *addr_of_mut!($var) }
+    };
+}
+
+static_access!(LOCATIONS: Vec<Location>, locations, locations_mut);
+static_access!(ACTIONS: Vec<Action>, actions, actions_mut);
+static_access!(ACTIVE_CHUNKS: Vec<usize>, active_chunks, active_chunks_mut);
+static_access!(CONFLICT_GRID: Option<ChunkedGrid<Conflict>>, conflict_grid, conflict_grid_mut);
+static_access!(OUTCOMES: Vec<Option<Outcome>>, outcomes, outcomes_mut);
+static_access!(ORDER: Vec<EntityId>, order, order_mut);
+static_access!(PROFILER: Option<Profiler>, profiler, profiler_mut);
+static_access!(STATS: Option<StatsRecorder>, stats, stats_mut);
+static_access!(EVENT_LOG: Option<EventLogWriter>, event_log, event_log_mut);
+static_access!(HISTORY: Option<History>, history, history_mut);
+static_access!(RENDER_WORKER: Option<Worker>, render_worker_mut);
+
+/// The number of completed calls to step.
+pub fn tick() -> u64 {
+    unsafe { TICK }
+}
+
+/// Statistics of how long the phase took over the profiler's window of recent ticks.
+pub fn phase_stats(phase: Phase) -> FrameStats {
+    unsafe { profiler().as_ref().map_or(FrameStats::default(), |profiler| profiler.stats(phase)) }
+}
+
+/// Writes the profiler's window of recent ticks as CSV and as a Chrome trace.
+pub fn export_profile(csv_path: &str, trace_path: &str) -> std::io::Result<()> {
+    unsafe {
+        let profiler = profiler().as_ref().unwrap();
+        profiler.write_csv(csv_path)?;
+        profiler.write_chrome_trace(trace_path)
+    }
+}
+
+pub fn entity_count() -> usize {
+    unsafe { (*addr_of!(WORLD)).as_ref().map_or(0, |world| world.entity_count()) }
+}
 
 fn is_drawing_enabled() -> bool {
     unsafe { DRAWING_ENABLED }
 }
 
-fn is_initialised() -> bool {
-    unsafe { WORLD.is_some() }
+/// The topology of the world, or Moore if there is no world.
+pub fn topology() -> Topology {
+    unsafe { (*addr_of!(WORLD)).as_ref().map_or(Topology::Moore, |world| world.topology()) }
+}
+
+pub fn update_scheme() -> UpdateScheme {
+    unsafe { UPDATE_SCHEME }
+}
+
+/// Safety: Don't call while step is running.
+pub fn set_update_scheme(scheme: UpdateScheme) {
+    unsafe { UPDATE_SCHEME = scheme }
+}
+
+pub fn color_mode() -> ColorMode {
+    unsafe { COLOR_MODE }
+}
+
+pub fn set_color_mode(color_mode: ColorMode) {
+    unsafe { COLOR_MODE = color_mode }
+}
+
+pub fn heatmap_kind() -> HeatmapKind {
+    unsafe { (*addr_of!(HEATMAP)).as_ref().map_or(HeatmapKind::Occupancy, |heatmap| heatmap.kind()) }
+}
+
+pub fn heatmap_overlay() -> OverlayMode {
+    unsafe { (*addr_of!(HEATMAP)).as_ref().map_or(OverlayMode::Off, |heatmap| heatmap.overlay()) }
+}
+
+/// Safety: Don't call while step is running, as the draw thread reads HEATMAP.
+pub fn set_heatmap_kind(kind: HeatmapKind) {
+    unsafe { heatmap_mut().set_kind(kind) }
+}
+
+/// Safety: Don't call while step is running, as the draw thread reads HEATMAP.
+pub fn set_heatmap_overlay(overlay: OverlayMode) {
+    unsafe { heatmap_mut().set_overlay(overlay) }
+}
+
+/// Safety: Don't call while step is running, as the draw thread reads HEATMAP.
+pub fn reset_heatmap() {
+    unsafe { heatmap_mut().reset() }
+}
+
+/// Safety: Don't call while step is running, as the draw thread reads HEATMAP.
+pub fn set_heatmap_decay(decay: f32) {
+    unsafe { heatmap_mut().set_decay(decay) }
+}
+
+/// Sets whether the heatmap records anything. It is recorded whether or not its overlay is shown, so that it
+/// can be looked at any time, which costs time every tick unless it is turned off.
+/// Safety: Don't call while step is running.
+pub fn set_heatmap_enabled(enabled: bool) {
+    unsafe { HEATMAP_ENABLED = enabled }
+}
+
+/// Sets how many of the most recent ticks phase timings are kept for, discarding any recorded so far.
+pub fn set_profile_window(window: usize) {
+    unsafe { PROFILER = Some(Profiler::new(window)) }
+}
+
+/// Sets whether steps run in parallel on a pool of the given size, or sequentially on the calling thread.
+/// Safety: Don't call while step is running.
+pub fn set_execution(execution: Execution) -> Result<(), String> {
+    execution::set(execution)
+}
+
+pub fn set_drawing_enabled(enabled: bool) {
+    unsafe { DRAWING_ENABLED = enabled }
+}
+
+/// Removes the world from the processor, which can then be initialised again.
+pub fn take_world() -> Option<World> {
+    unsafe { (*addr_of_mut!(WORLD)).take() }
+}
+
+/// Starts writing statistics to a CSV file at the given path every interval ticks, replacing any previous file.
+pub fn enable_stats(path: &str, interval: u64) -> std::io::Result<()> {
+    let recorder = StatsRecorder::create(path, interval)?;
+    unsafe { STATS = Some(recorder) }
+    Ok(())
+}
+
+pub fn disable_stats() {
+    unsafe { STATS = None }
+}
+
+pub fn is_stats_enabled() -> bool {
+    unsafe { stats().is_some() }
+}
+
+/// Starts recording every entity's action and outcome to the given directory, along with a snapshot of the world now.
+/// Safety: Don't call while step is running.
+pub fn start_recording(dir: &str) -> std::io::Result<()> {
+    let writer = unsafe { EventLogWriter::create(dir, world_ref(), TICK)? };
+    unsafe { EVENT_LOG = Some(writer) }
+    Ok(())
+}
+
+pub fn stop_recording() {
+    unsafe { EVENT_LOG = None }
+}
+
+pub fn is_recording() -> bool {
+    unsafe { event_log().is_some() }
+}
+
+/// Starts keeping the given number of past ticks, which can then be returned to with rewind_to.
+/// Safety: Don't call while step is running.
+pub fn enable_history(capacity: usize) {
+    unsafe { HISTORY = Some(History::new(world_ref(), TICK, capacity)) }
+}
+
+/// The earliest and latest ticks that can be returned to with rewind_to.
+pub fn history_range() -> Option<(u64, u64)> {
+    unsafe { history().as_ref().map(|history| (history.earliest_tick(), history.latest_tick())) }
+}
+
+/// Replaces the world with how it was (or, after rewinding, will be) at the given tick in the history.
+/// Stepping from an earlier tick starts a new branch, discarding the ticks after it.
+/// Recording stops, as the event log can't follow the jump.
+/// Safety: Don't call while step is running.
+pub fn rewind_to(tick: u64) -> Result<(), String> {
+    unsafe {
+        let history = history().as_ref().ok_or("history is not enabled")?;
+        if !history.contains(tick) {
+            return Err(format!("tick {} is not in the history", tick));
+        }
+        if tick == TICK { return Ok(()) }
+
+        if tick != TICK + 1 || !history.step_forward(world_mut(), TICK) {
+            WORLD = Some(history.world_at(tick).unwrap());
+        }
+        TICK = tick;
+        if event_log_mut().take().is_some() {
+            println!("stopped recording, as the world was rewound");
+        }
+    }
+    Ok(())
 }
 
-unsafe fn action_at(location: &Location) -> &Option<Action> {
-    ACTION_GRID.get_unchecked(location.index())
+/// The entity drawn at the given pixel, if any.
+pub fn entity_id_at(pixel_x: usize, pixel_y: usize) -> Option<EntityId> {
+    unsafe {
+        let world = (*addr_of!(WORLD)).as_ref()?;
+        let (x, y) = world.topology().cell_at_pixel(pixel_x, pixel_y, world.width(), world.height())?;
+        world.get_entity_id(&Location::at(x, y, world))
+    }
 }
 
-unsafe fn action_at_mut(location: &Location) -> &mut Option<Action> {
-    ACTION_GRID.get_unchecked_mut(location.index())
+/// A copy of the entity with the given id as it is now.
+pub fn inspect_entity(id: EntityId) -> Option<Entity> {
+    unsafe { (*addr_of!(WORLD)).as_ref()?.entity(id).map(|entity| entity.to_entity()) }
 }
 
+fn is_initialised() -> bool {
+    unsafe { (*addr_of!(WORLD)).is_some() }
+}
+
+/// Safety: CONFLICT_GRID must not be having chunks allocated.
 unsafe fn conflict_at(location: &Location) -> &Conflict {
-    CONFLICT_GRID.get_unchecked(location.index())
+    conflict_grid().as_ref().unwrap().get_allocated(location.x(), location.y())
+        .expect("conflict chunks should be allocated for every active chunk")
+}
+
+/// Safety: OUTCOMES must not be being mutated.
+unsafe fn outcome_of(id: EntityId) -> Outcome {
+    outcomes()[id].expect("there should be an outcome for this entity")
 }
 
-unsafe fn conflict_at_mut(location: &Location) -> &mut Conflict {
-    CONFLICT_GRID.get_unchecked_mut(location.index())
+/// Safety: WORLD must not be being mutated.
+unsafe fn world_ref() -> &'static World {
+    (*addr_of!(WORLD)).as_ref().unwrap()
 }
 
-unsafe fn outcome_at(location: &Location) -> &Option<Outcome> {
-    OUTCOME_GRID.get_unchecked(location.index())
+/// Safety: Nothing else may be using WORLD.
+unsafe fn world_mut() -> &'static mut World {
+    (*addr_of_mut!(WORLD)).as_mut().unwrap()
 }
 
-unsafe fn outcome_at_mut(location: &Location) -> &mut Option<Outcome> {
-    OUTCOME_GRID.get_unchecked_mut(location.index())
+/// Safety: HEATMAP must not be being mutated.
+unsafe fn heatmap_ref() -> &'static Heatmap {
+    (*addr_of!(HEATMAP)).as_ref().unwrap()
 }
 
-pub fn init(world: World) -> Result<(), ()> {
-    if is_initialised() { return Err(()) }
+/// Safety: Nothing else may be using HEATMAP.
+unsafe fn heatmap_mut() -> &'static mut Heatmap {
+    (*addr_of_mut!(HEATMAP)).as_mut().unwrap()
+}
 
-    let size = world.width() * world.height();
+/// The bytes held by the scratch storage used while stepping.
+pub fn scratch_bytes() -> usize {
     unsafe {
+        locations().capacity() * size_of::<Location>()
+            + actions().capacity() * size_of::<Action>()
+            + active_chunks().capacity() * size_of::<usize>()
+            + conflict_grid().as_ref().map_or(0, |grid| grid.allocated_cells()) * size_of::<Conflict>()
+            + outcomes().capacity() * size_of::<Option<Outcome>>()
+            + order().capacity() * size_of::<EntityId>()
+    }
+}
+
+pub fn init(world: World) -> Result<(), String> {
+    init_at_tick(world, 0)
+}
+
+/// Initialises the processor with a world that has already been stepped to the given tick, e.g. from a snapshot.
+/// Fails if it already has a world, which must be taken first.
+pub fn init_at_tick(world: World, tick: u64) -> Result<(), String> {
+    if is_initialised() {
+        return Err(String::from("the processor already has a world, which must be taken before replacing it"));
+    }
+
+    unsafe {
+        HEATMAP = Some(Heatmap::new(world.width(), world.height(), world.topology(), HEATMAP_DECAY));
+        CONFLICT_GRID = Some(ChunkedGrid::new(world.width(), world.height()));
+        PROFILER = Some(Profiler::new(PROFILE_WINDOW));
+        TICK = tick;
         WORLD = Some(world);
-        LOCATIONS = Vec::with_capacity(size);
-        ACTION_GRID = Vec::with_capacity(size);
-        ACTION_GRID.resize_with(size, || { None });
-        CONFLICT_GRID = Vec::with_capacity(size);
-        CONFLICT_GRID.resize_with(size, || { Conflict::none() });
-        OUTCOME_GRID = Vec::with_capacity(size);
-        OUTCOME_GRID.resize_with(size, || { None });
+        LOCATIONS = Vec::new();
+        ACTIONS = Vec::new();
+        ACTIVE_CHUNKS = Vec::new();
+        OUTCOMES = Vec::new();
+        ORDER = Vec::new();
+        // A history of another world can't be continued.
+        HISTORY = None;
     }
     Ok(())
 }
 
-/// Safety: Don't call while WORLD is being mutated.
+/// # Safety
+/// Don't call while WORLD is being mutated.
 pub unsafe fn draw() {
     if !is_drawing_enabled() { return }
 
     graphics_window::clear(Color::BLACK);
 
-    WORLD.as_ref().unwrap().iter_entities_par().for_each(|entity| {
-        let location = &entity.location;
-        let x = location.x();
-        let y = location.y();
-        let pixel_color = entity.pixel_color();
-        graphics_window::draw_pixel(x, y, pixel_color);
-    });
-}
+    let heatmap = heatmap_ref();
+    if heatmap.overlay() == OverlayMode::Only {
+        heatmap.draw();
+        return;
+    }
 
-pub fn step() {
-    unsafe {
-        let draw_thread = std::thread::spawn(|| {
-            // Safety:
-            // Reads: WORLD
-            // Mutates: -
-            draw();
+    let world = world_ref();
+    let topology = world.topology();
+    if world.wall_count() > 0 {
+        execution::for_each(&world.walls(), |wall| {
+            topology.draw_cell(wall.x(), wall.y(), WALL_COLOR);
         });
+    }
 
-        // Safety:
-        // Reads: -
-        // Mutates: LOCATIONS, ACTION_GRID, CONFLICT_GRID, OUTCOME_GRID
-        clean_up();
+    let color_mode = color_mode();
+    // Clusters depend on every entity at once, so they are found before drawing any.
+    let clusters = (color_mode == ColorMode::Cluster).then(|| Clusters::find(world));
+    match (world.entities(), &clusters) {
+        (_, Some(clusters)) => execution::for_each_indexed(&world.entities().locations(), |id, location| {
+            topology.draw_cell(location.x(), location.y(), ColorMode::cluster_color(clusters, id));
+        }),
+        (EntityStorage::Aos(entities), None) => execution::for_each(entities, |entity| {
+            let location = &entity.location;
+            let x = location.x();
+            let y = location.y();
+            let pixel_color = entity.pixel_color(color_mode);
+            topology.draw_cell(x, y, pixel_color);
+        }),
+        (EntityStorage::Soa(columns), None) => execution::for_each_indexed(columns.locations(), |id, location| {
+            let pixel_color = columns.get(id).unwrap().pixel_color(color_mode);
+            topology.draw_cell(location.x(), location.y(), pixel_color);
+        }),
+    }
 
-        // Safety:
-        // Reads: WORLD
-        // Mutates: LOCATIONS
-        get_locations_for_processing();
+    heatmap.draw();
+}
 
-        // Safety:
-        // Reads: LOCATIONS, WORLD
-        // Mutates: ACTION_GRID, CONFLICT_GRID
-        determine_actions();
+/// Draws the world as it is now, for frames where no step is drawing.
+pub fn render() {
+    // Safety: WORLD is only mutated during step, which is not running.
+    unsafe { draw() }
+}
 
-        // Safety:
-        // Reads: LOCATIONS, ACTION_GRID, CONFLICT_GRID
-        // Mutates: OUTCOME_GRID
-        resolve_conflicts();
+/// Advances the world by one tick.
+/// If draw is set, the world is drawn as it was at the start of the tick, in parallel with the tick.
+pub fn step(draw: bool) {
+    unsafe { step_unchecked(draw) }
+}
 
-        // Safety:
-        // Reads: LOCATIONS, WORLD, ACTION_GRID
-        // Mutates: OUTCOME_GRID
-        determine_outcomes();
+/// Safety: Only call from step.
+unsafe fn step_unchecked(draw: bool) {
+    let profiler = profiler_mut().as_mut().unwrap();
+    profiler.begin_tick(TICK);
+    if let Some(history) = history_mut().as_mut() {
+        history.truncate_after(TICK);
+    }
+    let step_start = Instant::now();
 
-        // Safety: WORLD will no longer be read by draw thread.
-        draw_thread.join().unwrap();
+    // Safety:
+    // Reads: WORLD, HEATMAP
+    // Mutates: -
+    // When sequential, drawing is done here rather than alongside the step, so only one thread is used.
+    if draw && execution::is_sequential() {
+        let draw_start = Instant::now();
+        self::draw();
+        profiler.record(Phase::Draw, draw_start, Instant::now());
+    } else if draw {
+        render_worker_mut().get_or_insert_with(|| Worker::spawn("render", || {
+            execution::install(|| unsafe { self::draw() })
+        })).start();
+    }
+
+    // The phases either side of joining the render worker each run wholly on the pool, so that they aren't
+    // handed over to it one by one. The join itself happens on this thread, as the render worker may need the pool.
+    execution::install(|| determine_outcomes_phases(profiler));
+
+    // Safety: WORLD and HEATMAP will no longer be read by the render worker.
+    if let Some(worker) = render_worker_mut().as_mut().filter(|worker| worker.is_running()) {
+        let (draw_start, draw_end) = profiler.time(Phase::DrawJoin, || worker.join());
+        profiler.record(Phase::Draw, draw_start, draw_end);
+    }
+
+    execution::install(|| apply_outcomes_phases(profiler));
+
+    TICK += 1;
+    if let Some(history) = history_mut().as_mut() {
+        history.offer_checkpoint(world_ref(), TICK);
+    }
+    profiler.record(Phase::Step, step_start, Instant::now());
+    profiler.end_tick();
+}
+
+/// Safety: Only call from step.
+unsafe fn determine_outcomes_phases(profiler: &mut Profiler) {
+    // Safety:
+    // Reads: WORLD
+    // Mutates: LOCATIONS, ACTIONS, CONFLICT_GRID, OUTCOMES
+    profiler.time(Phase::CleanUp, || clean_up());
+
+    // Safety:
+    // Reads: WORLD
+    // Mutates: LOCATIONS
+    profiler.time(Phase::GetLocations, || get_locations_for_processing());
+
+    // Asynchronous schemes change the world as entities act, so they wait until the render worker has been joined.
+    if UPDATE_SCHEME != UpdateScheme::Synchronous { return }
+
+    // Safety:
+    // Reads: LOCATIONS, WORLD
+    // Mutates: ACTIONS, ACTIVE_CHUNKS, CONFLICT_GRID
+    profiler.time(Phase::DetermineActions, || determine_actions());
+
+    // Safety:
+    // Reads: LOCATIONS, ACTIONS, CONFLICT_GRID
+    // Mutates: OUTCOMES
+    profiler.time(Phase::ResolveConflicts, || resolve_conflicts());
+
+    // Safety:
+    // Reads: LOCATIONS, WORLD, ACTIONS
+    // Mutates: OUTCOMES
+    profiler.time(Phase::DetermineOutcomes, || determine_outcomes());
+}
+
+/// Safety: Only call from step, once the render worker has been joined.
+unsafe fn apply_outcomes_phases(profiler: &mut Profiler) {
+    let synchronous = UPDATE_SCHEME == UpdateScheme::Synchronous;
 
-        // Safety:
-        // Reads: LOCATIONS, OUTCOME_GRID
-        // Mutates: WORLD
-        apply_outcomes()
+    // Safety:
+    // Reads: LOCATIONS
+    // Mutates: WORLD, ACTIONS, OUTCOMES, ORDER, ACTIVE_CHUNKS, CONFLICT_GRID
+    if !synchronous {
+        profiler.time(Phase::UpdateAsynchronously, || update_asynchronously());
+    }
+
+    // Safety:
+    // Reads: LOCATIONS, ACTIVE_CHUNKS, WORLD, ACTIONS, CONFLICT_GRID, OUTCOMES
+    // Mutates: HEATMAP
+    profiler.time(Phase::RecordHeatmap, || record_heatmap());
+
+    // Safety:
+    // Reads: LOCATIONS, ACTIONS, OUTCOMES, ORDER
+    // Mutates: EVENT_LOG
+    if event_log().is_some() {
+        profiler.time(Phase::RecordEvents, || record_events());
+    }
+
+    // Safety:
+    // Reads: OUTCOMES, ORDER
+    // Mutates: HISTORY
+    if history().is_some() {
+        profiler.time(Phase::RecordHistory, || record_history());
+    }
+
+    // Safety:
+    // Reads: OUTCOMES
+    // Mutates: WORLD
+    if synchronous {
+        profiler.time(Phase::ApplyOutcomes, || apply_outcomes());
+    }
+
+    // Safety:
+    // Reads: LOCATIONS, WORLD, ACTIONS, CONFLICT_GRID, OUTCOMES
+    // Mutates: STATS
+    if stats().as_ref().is_some_and(|stats| stats.is_sampled(TICK)) {
+        profiler.time(Phase::CollectStats, || collect_stats());
     }
 }
 
-/// Safety: This function mutates LOCATIONS, ACTION_GRID, CONFLICT_GRID and OUTCOME_GRID.
+/// Safety: This function reads from WORLD and mutates LOCATIONS, ACTIONS, CONFLICT_GRID and OUTCOMES.
+/// In sparse worlds only the cells of CONFLICT_GRID targeted by last tick's moves are cleared, so that the
+/// cost of this phase depends on the number of entities rather than the size of the world.
 unsafe fn clean_up() {
-    LOCATIONS.par_iter().for_each(|location| {
-        action_at_mut(location).take();
-        outcome_at_mut(location).take();
-    });
-    CONFLICT_GRID.par_iter_mut().for_each(|conflict| {
-        conflict.clear()
-    });
-    LOCATIONS.clear();
+    let sparse = locations().len() * SPARSE_CLEAN_RATIO < conflict_grid().as_ref().unwrap().allocated_cells();
+    clear_conflicts(sparse);
+    locations_mut().clear();
+    actions_mut().clear();
+    outcomes_mut().clear();
+    order_mut().clear();
+}
+
+/// Safety: This function reads from WORLD, LOCATIONS and ACTIONS and mutates CONFLICT_GRID.
+/// Clears the cells targeted by last tick's moves one by one if sparse, or else every allocated cell.
+unsafe fn clear_conflicts(sparse: bool) {
+    if sparse {
+        execution::for_each_indexed(locations(), |id, location| {
+            clear_conflicts_for_entity(id, location);
+        });
+    } else {
+        execution::for_each(&conflict_grid().as_ref().unwrap().allocated_chunks(), |(_, conflicts)| {
+            conflicts.iter().for_each(Conflict::clear)
+        });
+    }
 }
 
 /// Safety: This function reads from WORLD and mutates LOCATIONS;
 unsafe fn get_locations_for_processing() {
-    LOCATIONS = WORLD.as_ref().unwrap().iter_entities_par()
-        .map(|entity| entity.location)
-        .collect();
+    LOCATIONS = world_ref().entities().locations().into_owned();
 }
 
-/// Safety: This function reads from LOCATIONS and WORLD and mutates ACTION_GRID and CONFLICT_GRID.
+/// Safety: This function reads from LOCATIONS and WORLD and mutates ACTIONS, ACTIVE_CHUNKS and CONFLICT_GRID.
+/// Conflicts are only added once every action is known, so that the chunks they are in can be allocated first.
 unsafe fn determine_actions() {
-    LOCATIONS.par_iter().for_each(|location| {
-        determine_action_for_location(location);
+    ACTIONS = execution::map_indexed(locations(), |id, location| {
+        determine_action_for_location(id, location)
+    });
+    activate_chunks();
+
+    execution::for_each_indexed(locations(), |id, location| {
+        add_conflicts_for_entity(id, location);
     });
 }
 
-/// Safety: This function reads from LOCATIONS, ACTION_GRID and CONFLICT_GRID and mutates OUTCOME_GRID.
+/// Safety: This function reads from LOCATIONS, WORLD and ACTIONS and mutates ACTIVE_CHUNKS and CONFLICT_GRID.
+/// Conflict chunks that are no longer active are freed, so that CONFLICT_GRID only holds the chunks in use.
+unsafe fn activate_chunks() {
+    let world = world_ref();
+    let entered_chunks = execution::map_indexed(locations(), |id, location| {
+        chunks_entered_by_entity(id, location)
+    });
+    ACTIVE_CHUNKS = world.populated_chunks();
+    active_chunks_mut().extend(entered_chunks.concat());
+    active_chunks_mut().sort_unstable();
+    active_chunks_mut().dedup();
+    let conflict_grid = conflict_grid_mut().as_mut().unwrap();
+    conflict_grid.retain_chunks(active_chunks());
+    for chunk in active_chunks() {
+        conflict_grid.allocate(*chunk);
+    }
+}
+
+/// Safety: This function reads from LOCATIONS and mutates WORLD, ACTIONS, OUTCOMES, ORDER, ACTIVE_CHUNKS and
+/// CONFLICT_GRID. Entities act in groups, each group's outcomes applied before the next group acts.
+/// No two entities in a group can reach the same cell, so there are no conflicts.
+/// The order is worked out in ORDER itself, which keeps its capacity from one tick to the next.
+unsafe fn update_asynchronously() {
+    let world = world_ref();
+    let order = order_mut();
+    order.extend(0..locations().len());
+    ACTIONS = vec![Action::Wait; locations().len()];
+    OUTCOMES = vec![None; locations().len()];
+    match UPDATE_SCHEME {
+        UpdateScheme::Synchronous => unreachable!("synchronous steps apply their outcomes together"),
+        UpdateScheme::RandomSequential => {
+            for i in (1..order.len()).rev() {
+                order.swap(i, (rng_buffer::next() * (i + 1) as f64) as usize % (i + 1));
+            }
+            order.iter().for_each(|id| act_together(std::slice::from_ref(id)));
+        }
+        UpdateScheme::Checkerboard => {
+            let class_of = |id: &EntityId| update_scheme::checkerboard_class(world, &locations()[*id]);
+            // The sort is stable, so each class acts in id order.
+            order.sort_by_key(class_of);
+            order.chunk_by(|a, b| class_of(a) == class_of(b)).for_each(|group| act_together(group));
+        }
+    }
+    activate_chunks();
+}
+
+/// Safety: This function reads from LOCATIONS and mutates WORLD, ACTIONS and OUTCOMES.
+/// The entities act as if alone, and then their outcomes are applied, so no two of them may reach the same cell.
+unsafe fn act_together(group: &[EntityId]) {
+    // Safety: WORLD is only read while the group acts, and only mutated once it has.
+    let apply = |id: EntityId, (action, outcome): (Action, Outcome)| {
+        world_mut().apply_outcome(id, &outcome).expect("entities acting alone should only move into empty cells");
+        actions_mut()[id] = action;
+        outcomes_mut()[id] = Some(outcome);
+    };
+    if let [id] = group {
+        apply(*id, act_alone(*id));
+    } else {
+        for (id, acted) in group.iter().zip(execution::map(group, |id| act_alone(*id))) {
+            apply(*id, acted);
+        }
+    }
+}
+
+/// Safety: This function reads from LOCATIONS and WORLD.
+/// The action and outcome of an entity as if no other entity were acting, which the entity hasn't yet this tick.
+unsafe fn act_alone(id: EntityId) -> (Action, Outcome) {
+    let world = world_ref();
+    let action = determine_action_for_location(id, &locations()[id]);
+    let entity = world.entity(id).expect("entity should exist");
+    (action, action.resolve(&entity, world))
+}
+
+/// Safety: This function reads from LOCATIONS, ACTIONS and CONFLICT_GRID and mutates OUTCOMES.
 unsafe fn resolve_conflicts() {
-    LOCATIONS.par_iter().for_each(|location| {
-        resolve_conflicts_for_location(location);
+    OUTCOMES = execution::map_indexed(locations(), |id, location| {
+        resolve_conflicts_for_entity(id, location)
     });
 }
 
-/// Safety: This function reads from LOCATIONS, WORLD and ACTION_GRID and mutates OUTCOME_GRID.
+/// Safety: This function reads from LOCATIONS, WORLD and ACTIONS and mutates OUTCOMES.
 unsafe fn determine_outcomes() {
-    LOCATIONS.par_iter().for_each(|location| {
-        determine_outcomes_for_location(location);
+    execution::for_each_indexed_mut(outcomes_mut(), |id, outcome| {
+        determine_outcome_for_entity(id, outcome);
     });
 }
 
-/// Safety: This function reads from LOCATIONS and OUTCOME_GRID and mutates WORLD.
-unsafe fn apply_outcomes() {
-    LOCATIONS.par_iter().for_each(|location| {
-        apply_outcome_for_location(location);
+/// Safety: This function reads from LOCATIONS, ACTIVE_CHUNKS, WORLD, ACTIONS, CONFLICT_GRID and OUTCOMES and mutates HEATMAP.
+/// Does nothing while the heatmap is disabled.
+unsafe fn record_heatmap() {
+    if !HEATMAP_ENABLED { return }
+    let heatmap = heatmap_mut();
+    heatmap.allocate(active_chunks());
+    heatmap.apply_decay(active_chunks());
+    execution::for_each_indexed(locations(), |id, location| {
+        record_heatmap_for_entity(id, location);
     });
 }
 
-/// Safety: This function reads from WORLD and mutates ACTION_GRID and CONFLICT_GRID.
-unsafe fn determine_action_for_location(location: &Location) {
-    let entity =WORLD.as_ref().unwrap().get_entity(location)
-            .expect("entity should be at this location");
-    let action = entity.determine_action(WORLD.as_ref().unwrap());
+/// Safety: This function reads from LOCATIONS, ACTIONS, OUTCOMES and ORDER and mutates EVENT_LOG.
+/// Events are written in the order the entities acted in, so that replaying them moves entities one at a time
+/// into cells that were emptied earlier in the tick. Busy entities are left out, as replaying works them out.
+unsafe fn record_events() {
+    let acted = |id: &EntityId| !matches!(actions()[*id], Action::Busy);
+    let event = |id: EntityId| Event { entity: id, action: actions()[id], outcome: outcome_of(id) };
+    let events: Vec<Event> = if order().is_empty() {
+        (0..locations().len()).filter(acted).map(event).collect()
+    } else {
+        order().iter().copied().filter(acted).map(event).collect()
+    };
+    if let Err(error) = event_log_mut().as_mut().unwrap().write_tick(TICK, &events) {
+        println!("failed to write events, so stopped recording them: {}", error);
+        EVENT_LOG = None;
+    }
+}
 
-    match action.conflicting_directions() {
-        None => {}
-        Some(directions) => for direction in directions {
-            let conflict_location = WORLD.as_ref().unwrap().add(&entity.location, &direction);
-           conflict_at_mut(&conflict_location).add_from(&direction);
+/// Safety: This function reads from OUTCOMES and ORDER and mutates HISTORY.
+unsafe fn record_history() {
+    let outcomes = execution::map(outcomes(), |outcome| {
+        outcome.expect("there should be an outcome for this entity").to_code()
+    });
+    let order = order().iter().map(|id| *id as u32).collect();
+    history_mut().as_mut().unwrap().push(TickDelta::new(outcomes, order));
+}
+
+/// Safety: This function reads from LOCATIONS, WORLD, ACTIONS, CONFLICT_GRID and OUTCOMES and mutates STATS.
+/// It must run after apply_outcomes, as energy and age are taken from the updated entities.
+unsafe fn collect_stats() {
+    let world = world_ref();
+    let outcome_stats = execution::sum_indexed(locations(), |id, location| stats_for_entity(id, location));
+    let entity_stats = match world.entities() {
+        EntityStorage::Aos(entities) => execution::sum(entities, |entity| TickStats {
+            entities: 1,
+            total_energy: entity.energy as f64,
+            total_age: entity.age as u64,
+            ..TickStats::default()
+        }),
+        EntityStorage::Soa(columns) => TickStats {
+            entities: columns.len(),
+            total_energy: execution::sum(columns.energies(), |energy| *energy as f64),
+            total_age: execution::sum(columns.ages(), |age| *age as u64),
+            ..TickStats::default()
+        },
+    };
+
+    let clusters = Clusters::find(world);
+    let cluster_stats = TickStats {
+        clusters: clusters.count(),
+        largest_cluster: clusters.largest(),
+        cluster_sizes: clusters.size_histogram(),
+        ..TickStats::default()
+    };
+
+    let stats = TickStats { tick: TICK, ..outcome_stats + entity_stats + cluster_stats };
+    if let Err(error) = stats_mut().as_mut().unwrap().write(&stats) {
+        println!("failed to write stats, so stopped recording them: {}", error);
+        STATS = None;
+    }
+}
+
+/// Safety: This function reads from OUTCOMES and mutates WORLD.
+unsafe fn apply_outcomes() {
+    // Safety: Outcomes only move entities into cells that were unoccupied and unconflicted, so one mover per cell.
+    world_mut().apply_outcomes(|id| outcome_of(id));
+}
+
+/// Safety: This function reads from WORLD and ACTIONS and mutates CONFLICT_GRID.
+/// The targets of a location don't depend on where entities are, so they are the same as when the action was determined.
+unsafe fn clear_conflicts_for_entity(id: EntityId, location: &Location) {
+    let Some(directions) = actions()[id].conflicting_directions() else { return };
+    for direction in directions {
+        if let Some(conflict_location) = world_ref().target(location, &direction) {
+            conflict_at(&conflict_location).clear();
         }
     }
+}
+
+/// Safety: This function reads from WORLD.
+unsafe fn determine_action_for_location(id: EntityId, location: &Location) -> Action {
+    let world = world_ref();
+    // Checked before getting the entity, so that busy entities cost as little as possible.
+    if world.entities().is_busy(id) { return Action::Busy }
+    let entity = world.get_entity(location)
+            .expect("entity should be at this location");
+    entity.determine_action(world)
+}
+
+/// Safety: This function reads from WORLD and ACTIONS.
+/// The chunks other than its own that the entity is trying to move into.
+unsafe fn chunks_entered_by_entity(id: EntityId, location: &Location) -> Vec<usize> {
+    let world = world_ref();
+    if !world.is_on_chunk_edge(location) { return Vec::new() }
+    let Some(directions) = actions()[id].conflicting_directions() else { return Vec::new() };
+    directions.iter()
+        .filter_map(|direction| world.target(location, direction))
+        .map(|target| world.chunk_of(&target))
+        .filter(|chunk| *chunk != world.chunk_of(location))
+        .collect()
+}
 
-    action_at_mut(&entity.location).replace(action);
+/// Safety: This function reads from WORLD and ACTIONS and mutates CONFLICT_GRID.
+unsafe fn add_conflicts_for_entity(id: EntityId, location: &Location) {
+    let Some(directions) = actions()[id].conflicting_directions() else { return };
+    for direction in directions {
+        // Moves that the boundary or terrain block can't conflict with anything.
+        if let Some(conflict_location) = world_ref().target(location, &direction) {
+            conflict_at(&conflict_location).add_from(&direction);
+        }
+    }
 }
 
-/// Safety: This function reads from ACTION_GRID and CONFLICT_GRID and mutates OUTCOME_GRID.
-unsafe fn resolve_conflicts_for_location(location: &Location) {
-    let action = action_at(location).as_ref()
-        .expect("there should be an action at this location");
-    match action.conflicting_directions() {
+/// Safety: This function reads from ACTIONS and CONFLICT_GRID.
+/// The outcome is Blocked if the entity moves into a conflicted cell, and otherwise not yet known.
+unsafe fn resolve_conflicts_for_entity(id: EntityId, location: &Location) -> Option<Outcome> {
+    match actions()[id].conflicting_directions() {
         None => {}
         Some(directions) => for direction in &directions {
-            let conflict_direction = WORLD.as_ref().unwrap().add(location, direction);
+            let Some(conflict_direction) = world_ref().target(location, direction) else { continue };
             if conflict_at(&conflict_direction).is_conflicted() {
-                outcome_at_mut(location).replace(Outcome::Blocked);
-                break;
+                return Some(Outcome::Blocked);
             }
         }
     }
+    None
 }
 
-/// Safety: This function reads from WORLD and ACTION_GRID and mutates OUTCOME_GRID.
-unsafe fn determine_outcomes_for_location(location: &Location) {
-    let entity = WORLD.as_ref().unwrap().get_entity(location)
-            .expect("entity should be at this location");
-    let action = action_at(location).as_ref()
-        .expect("there should be an action at this location");
-    if outcome_at(location).is_none() { // Otherwise the outcome here is from conflict resolution, which takes precedence.
-        let outcome = action.resolve(entity, WORLD.as_ref().unwrap());
-        outcome_at_mut(location).replace(outcome);
+/// Safety: This function reads from LOCATIONS, WORLD and ACTIONS.
+unsafe fn determine_outcome_for_entity(id: EntityId, outcome: &mut Option<Outcome>) {
+    if outcome.is_none() { // Otherwise the outcome is from conflict resolution, which takes precedence.
+        let world = world_ref();
+        let entity = world.get_entity(&locations()[id])
+                .expect("entity should be at this location");
+        *outcome = Some(actions()[id].resolve(&entity, world));
     }
 }
 
-/// Safety: This function reads from OUTCOME_GRID and mutates WORLD.
-unsafe fn apply_outcome_for_location(location: &Location) {
-    let outcome = outcome_at(location).as_ref()
-        .expect("there should be an outcome at this location");
+/// Safety: This function reads from WORLD, ACTIONS, CONFLICT_GRID and OUTCOMES and mutates HEATMAP.
+/// Each cell is written by at most one entity: moves are only allowed into unoccupied, unconflicted cells,
+/// and blocking at a conflicted cell is recorded only by the first of the movers into it.
+unsafe fn record_heatmap_for_entity(id: EntityId, location: &Location) {
+    let world = world_ref();
+    let heatmap = heatmap_ref();
 
-    match outcome {
-        Outcome::Wait => {}
-        Outcome::Move(direction) => resolve_move(location, direction),
-        Outcome::Turn(facing) => resolve_turn(location, facing),
-        Outcome::Blocked => {}
+    match outcome_of(id) {
+        Outcome::Move(direction) => {
+            let destination = world.add(location, &direction);
+            heatmap.add_traffic(&destination);
+            heatmap.add_occupancy(&destination);
+        }
+        Outcome::Blocked => {
+            heatmap.add_occupancy(location);
+            if let Action::Move(direction) = actions()[id] {
+                let Some(target) = world.target(location, &direction) else { return };
+                let conflict = conflict_at(&target);
+                if conflict.is_conflicted() && conflict.is_first_from(&direction) {
+                    heatmap.add_blocking(&target, conflict.count());
+                }
+            }
+        }
+        Outcome::Wait | Outcome::Turn(_) | Outcome::Busy => heatmap.add_occupancy(location),
     }
 }
 
-/// Safety: This function mutates WORLD.
-unsafe fn resolve_move(location: &Location, direction: &Direction) {
-    WORLD.as_mut().unwrap().move_entity(location, direction)
-            .expect("entity should be at location and destination should be unoccupied");
-}
-
-/// Safety: This function mutates WORLD.
-unsafe fn resolve_turn(location: &Location, facing: &Direction) {
-    let mut entity = WORLD.as_mut().unwrap().get_entity_mut(location)
-            .expect("entity should be at this location");
-    entity.facing = *facing;
+/// Safety: This function reads from WORLD, ACTIONS, CONFLICT_GRID and OUTCOMES.
+unsafe fn stats_for_entity(id: EntityId, location: &Location) -> TickStats {
+    let mut stats = TickStats::default();
+    match outcome_of(id) {
+        Outcome::Blocked => stats.blocked = 1,
+        Outcome::Wait => stats.waits = 1,
+        Outcome::Move(_) => stats.moves = 1,
+        Outcome::Turn(_) => stats.turns = 1,
+        Outcome::Busy => stats.busy = 1,
+    }
+    // Each conflicted cell is counted once, by the first of the movers into it.
+    if let Action::Move(direction) = actions()[id] {
+        if let Some(target) = world_ref().target(location, &direction) {
+            let conflict = conflict_at(&target);
+            if conflict.is_conflicted() && conflict.is_first_from(&direction) {
+                stats.conflicts = 1;
+            }
+        }
+    }
+    stats
 }
 
+/// The moves into a cell this tick, as a bitmask of the sides they came from, clockwise from North.
+/// Bits are set from many tasks at once, so the mask is atomic.
 #[derive(Default)]
-struct Conflict {
-    north: bool,
-    northeast: bool,
-    east: bool,
-    southeast: bool,
-    south: bool,
-    southwest: bool,
-    west: bool,
-    northwest: bool,
-}
+struct Conflict(AtomicU8);
 
 impl Conflict {
-    #[inline]
-    fn none() -> Conflict {
-        Conflict::default()
-    }
-
-    fn add_from(&mut self, direction: &Direction) {
-        match direction {
-            Direction::North        => self.south = true,
-            Direction::Northeast    => self.southwest = true,
-            Direction::East         => self.west = true,
-            Direction::Southeast    => self.northwest = true,
-            Direction::South        => self.north = true,
-            Direction::Southwest    => self.northeast = true,
-            Direction::West         => self.east = true,
-            Direction::Northwest    => self.southeast = true,
-        }
+    fn add_from(&self, direction: &Direction) {
+        self.0.fetch_or(Self::flag_for(direction), Ordering::Relaxed);
+    }
+
+    fn clear(&self) {
+        self.0.store(0, Ordering::Relaxed);
     }
 
-    fn clear(&mut self) {
-        self.north = false;
-        self.northeast = false;
-        self.east = false;
-        self.southeast = false;
-        self.south = false;
-        self.southwest = false;
-        self.west = false;
-        self.northwest = false;
+    /// The conflict flag set by a move in the given direction.
+    fn flag_for(direction: &Direction) -> u8 {
+        1 << ((direction.index() + 4) % 8)
+    }
+
+    fn bits(&self) -> u8 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The number of moves into this cell.
+    fn count(&self) -> usize {
+        self.bits().count_ones() as usize
+    }
+
+    /// Whether a move in the given direction is the first (by compass order of the side it came from) into this cell.
+    fn is_first_from(&self, direction: &Direction) -> bool {
+        let bits = self.bits();
+        bits & bits.wrapping_neg() == Self::flag_for(direction)
     }
 
     fn is_conflicted(&self) -> bool {
-        let mut count = 0;
-        count += self.north as usize;
-        count += self.northeast as usize;
-        count += self.east as usize;
-        count += self.southeast as usize;
-        count += self.south as usize;
-        count += self.southwest as usize;
-        count += self.west as usize;
-        count += self.northwest as usize;
-        return count > 1;
+        self.count() > 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::ActionDurations;
+    use crate::behaviour::Behaviour;
+    use crate::entity_storage::EntityLayout;
+    use crate::snapshot;
+    use crate::test_world;
+    use crate::world::Terrain;
+
+    /// Initialises the processor with the world, first taking any world left behind by a test that failed.
+    fn start(world: World) {
+        take_world();
+        init(world).unwrap();
+    }
+
+    /// Steps the world through the processor for the given number of ticks, with the given execution and
+    /// update scheme, and returns it. Callers hold rng_buffer::lock_for_test, as the processor is shared.
+    fn run(world: World, ticks: u64, execution: Execution, scheme: UpdateScheme) -> World {
+        execution::set(execution).unwrap();
+        set_update_scheme(scheme);
+        set_drawing_enabled(false);
+        start(world);
+        for _ in 0..ticks {
+            step(false);
+        }
+        let world = take_world().unwrap();
+        set_update_scheme(UpdateScheme::Synchronous);
+        world
+    }
+
+    /// Everything about the world that a snapshot keeps, for comparing worlds.
+    fn snapshot_of(world: &World) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        snapshot::write(&mut bytes, world, 0).unwrap();
+        bytes
+    }
+
+    /// A world of Wanderers scattered at random from the seed, which also seeds rng_buffer for stepping it.
+    fn seeded_world(seed: u64, width: usize, height: usize, entity_count: u32) -> World {
+        rng_buffer::init_with_seed(seed);
+        let mut world = World::new(width, height);
+        test_world::load(&mut world, entity_count);
+        world
+    }
+
+    /// A world of the given size with an entity in every third cell.
+    fn scattered_world(width: usize, height: usize) -> World {
+        let mut world = World::new(width, height);
+        for i in (0..width * height).step_by(3) {
+            world.place_entity(Entity::new(i % width, i / width, &world)).unwrap();
+        }
+        world
+    }
+
+    /// Checks that the entity grid and the entities' locations agree, so no two entities share a cell.
+    fn assert_consistent(world: &World) {
+        for id in 0..world.entity_count() {
+            let location = world.entity(id).unwrap().location();
+            assert_eq!(world.get_entity_id(&location), Some(id), "entity {} isn't where the grid says", id);
+        }
+        let occupied = (0..world.height())
+            .flat_map(|y| (0..world.width()).map(move |x| (x, y)))
+            .filter(|(x, y)| world.get_entity_id(&Location::at(*x, *y, world)).is_some())
+            .count();
+        assert_eq!(occupied, world.entity_count());
+    }
+
+    /// Small enough to run under Miri, which checks the parallel phases for data races and undefined behaviour:
+    /// MIRIFLAGS=-Zmiri-ignore-leaks cargo +nightly miri test processor_small_world
+    /// The processor's threads are still running when the test ends, which Miri would otherwise report.
+    #[test]
+    fn processor_small_world() {
+        let _lock = rng_buffer::lock_for_test();
+        rng_buffer::init_with_seed(3);
+        let world = scattered_world(8, 6);
+        let world = run(world, 4, Execution::Parallel(2), UpdateScheme::Synchronous);
+        assert_eq!(world.entity_count(), 16);
+        assert_consistent(&world);
+    }
+
+    #[test]
+    fn rewinding_matches_the_world_as_it_was() {
+        let _lock = rng_buffer::lock_for_test();
+        rng_buffer::init_with_seed(5);
+        execution::set(Execution::Sequential).unwrap();
+        set_drawing_enabled(false);
+        start(scattered_world(20, 12));
+        // Small enough that the earliest ticks are forgotten, keeping checkpoints on both sides of the keyframe.
+        enable_history(50);
+        let mut snapshots = vec![unsafe { snapshot_of(world_ref()) }];
+        for _ in 0..100 {
+            step(false);
+            snapshots.push(unsafe { snapshot_of(world_ref()) });
+        }
+        assert!(snapshots[50] != snapshots[100], "the world should change, or there is nothing to rewind");
+        assert_eq!(history_range(), Some((50, 100)));
+        assert!(rewind_to(49).is_err());
+
+        for target in [99, 65, 64, 63, 50, 96, 100] {
+            rewind_to(target).unwrap();
+            assert_eq!(tick(), target);
+            assert!(unsafe { snapshot_of(world_ref()) } == snapshots[target as usize], "tick {} differs", target);
+        }
+
+        // Stepping forward through the history replays it.
+        rewind_to(60).unwrap();
+        for target in 61..=70 {
+            rewind_to(target).unwrap();
+            assert!(unsafe { snapshot_of(world_ref()) } == snapshots[target as usize], "tick {} differs", target);
+        }
+
+        // Stepping from a rewound tick branches, forgetting the ticks after it.
+        step(false);
+        assert_eq!(history_range(), Some((50, 71)));
+        assert!(rewind_to(72).is_err());
+        rewind_to(66).unwrap();
+        assert!(unsafe { snapshot_of(world_ref()) } == snapshots[66]);
+
+        take_world().unwrap();
+    }
+
+    #[test]
+    fn entities_act_once_per_interval_and_duration() {
+        let _lock = rng_buffer::lock_for_test();
+        let mut world = World::new(40, 8);
+        world.set_action_durations(ActionDurations { wait: 1, moving: 2, turn: 3, blocked: 1 });
+        // Cruisers moving east along rows of their own, so that they are never blocked.
+        let intervals = [1, 2, 5, 0];
+        for (row, interval) in intervals.into_iter().enumerate() {
+            let mut entity = Entity::new(0, row, &world);
+            entity.behaviour = Behaviour::Cruiser;
+            entity.facing = Direction::East;
+            entity.interval = interval;
+            world.place_entity(entity).unwrap();
+        }
+        // And one walled in, which turns after being blocked and is blocked after turning.
+        for (x, y) in [(19, 5), (20, 5), (21, 5), (19, 6), (21, 6), (19, 7), (20, 7), (21, 7)] {
+            world.set_terrain(&Location::at(x, y, &world), Terrain::Wall).unwrap();
+        }
+        let mut entity = Entity::new(20, 6, &world);
+        entity.behaviour = Behaviour::Cruiser;
+        entity.interval = 2;
+        world.place_entity(entity).unwrap();
+        let durations = world.action_durations();
+
+        execution::set(Execution::Sequential).unwrap();
+        set_drawing_enabled(false);
+        start(world);
+        let mut remaining = [0; 5];
+        let mut acted_on = vec![Vec::new(); 5];
+        for tick in 0..40 {
+            step(false);
+            for id in 0..5 {
+                let (action, outcome) = unsafe { (actions()[id], outcomes()[id].unwrap()) };
+                if remaining[id] > 0 {
+                    // A busy action is only given in place of determining one.
+                    assert!(matches!(action, Action::Busy), "entity {} acted on tick {} while busy", id, tick);
+                    assert!(matches!(outcome, Outcome::Busy));
+                    remaining[id] -= 1;
+                } else {
+                    assert!(!matches!(action, Action::Busy), "entity {} was busy on tick {}", id, tick);
+                    let interval = unsafe { world_ref().entity(id).unwrap().interval() };
+                    remaining[id] = interval.max(1) as u16 * durations.steps(&outcome) as u16 - 1;
+                    acted_on[id].push(tick);
+                }
+            }
+        }
+        take_world().unwrap();
+
+        assert_eq!(acted_on[0], (0..40).step_by(2).collect::<Vec<_>>());
+        assert_eq!(acted_on[1], (0..40).step_by(4).collect::<Vec<_>>());
+        assert_eq!(acted_on[2], (0..40).step_by(10).collect::<Vec<_>>());
+        assert_eq!(acted_on[3], acted_on[0]);
+        // Blocked for one step then turning for three, each taking twice as long at an interval of 2.
+        assert_eq!(acted_on[4], [0, 2, 8, 10, 16, 18, 24, 26, 32, 34]);
+    }
+
+    #[test]
+    fn asynchronous_schemes_never_put_two_entities_in_a_cell() {
+        let _lock = rng_buffer::lock_for_test();
+        for scheme in [UpdateScheme::RandomSequential, UpdateScheme::Checkerboard] {
+            let world = seeded_world(11, 30, 20, 300);
+            execution::set(Execution::Parallel(2)).unwrap();
+            set_update_scheme(scheme);
+            set_drawing_enabled(false);
+            start(world);
+            for _ in 0..50 {
+                step(false);
+                unsafe { assert_consistent(world_ref()) };
+                // Every entity acted exactly once.
+                let mut acted = unsafe { order().clone() };
+                acted.sort_unstable();
+                assert_eq!(acted, (0..300).collect::<Vec<_>>(), "{:?}", scheme);
+            }
+            take_world().unwrap();
+            set_update_scheme(UpdateScheme::Synchronous);
+        }
+    }
+
+    /// With more than one thread, entities acting at the same time take random numbers in whichever order their
+    /// threads get to them, so runs only match sequential ones where entities act one at a time. One thread
+    /// still goes through the pool and the parallel phases, taking the entities in order.
+    #[test]
+    fn same_seed_gives_the_same_world_in_parallel_and_sequential() {
+        let _lock = rng_buffer::lock_for_test();
+        for scheme in [UpdateScheme::Synchronous, UpdateScheme::RandomSequential, UpdateScheme::Checkerboard] {
+            let sequential = run(seeded_world(7, 60, 40, 1200), 40, Execution::Sequential, scheme);
+            let parallel = run(seeded_world(7, 60, 40, 1200), 40, Execution::Parallel(1), scheme);
+            assert!(snapshot_of(&sequential) == snapshot_of(&parallel), "{:?} differs on one thread", scheme);
+        }
+        let sequential = run(seeded_world(8, 60, 40, 1200), 40, Execution::Sequential, UpdateScheme::RandomSequential);
+        let parallel = run(seeded_world(8, 60, 40, 1200), 40, Execution::Parallel(2), UpdateScheme::RandomSequential);
+        assert!(snapshot_of(&sequential) == snapshot_of(&parallel), "random-sequential differs on two threads");
+    }
+
+    #[test]
+    fn entity_layouts_step_the_same() {
+        let _lock = rng_buffer::lock_for_test();
+        let worlds = [EntityLayout::Aos, EntityLayout::Soa].map(|layout| {
+            let mut world = seeded_world(4, 80, 60, 2000);
+            world.set_entity_layout(layout);
+            run(world, 60, Execution::Sequential, UpdateScheme::Synchronous)
+        });
+        assert!(worlds.iter().all(|world| snapshot_of(world) == snapshot_of(&worlds[0])));
+        // And the entities moved, so there was something to differ.
+        assert!(snapshot_of(&worlds[0]) != snapshot_of(&seeded_world(4, 80, 60, 2000)));
+    }
+
+    /// The chunks allocated in the conflict grid, and those in ACTIVE_CHUNKS.
+    type Chunks = (Vec<usize>, Vec<usize>);
+
+    /// Clears conflicts the sparse or the dense way after every step, rather than whichever clean_up would choose,
+    /// and returns the chunks the conflict grid and ACTIVE_CHUNKS were left with after each, and the world.
+    fn run_clearing(sparse: bool) -> (Vec<Chunks>, World) {
+        // Dense enough for plenty of conflicts, in a world of several chunks so that some are freed.
+        let mut world = seeded_world(6, 300, 140, 6000);
+        world.set_boundary(crate::world::Boundary::Solid);
+        execution::set(Execution::Sequential).unwrap();
+        set_drawing_enabled(false);
+        start(world);
+        let mut chunks = Vec::new();
+        for _ in 0..30 {
+            step(false);
+            unsafe {
+                clear_conflicts(sparse);
+                let conflict_grid = conflict_grid().as_ref().unwrap();
+                for (chunk, conflicts) in conflict_grid.allocated_chunks() {
+                    assert!(conflicts.iter().all(|conflict| conflict.bits() == 0), "chunk {} isn't clear", chunk);
+                }
+                let allocated = conflict_grid.allocated_chunks().iter().map(|(chunk, _)| *chunk).collect();
+                chunks.push((allocated, active_chunks().clone()));
+            }
+        }
+        (chunks, take_world().unwrap())
+    }
+
+    #[test]
+    fn sparse_and_dense_clean_up_leave_the_same_state() {
+        let _lock = rng_buffer::lock_for_test();
+        let (sparse_chunks, sparse_world) = run_clearing(true);
+        let (dense_chunks, dense_world) = run_clearing(false);
+        assert_eq!(sparse_chunks, dense_chunks);
+        assert!(snapshot_of(&sparse_world) == snapshot_of(&dense_world));
+    }
+
+    #[test]
+    fn chunks_left_empty_are_no_longer_active() {
+        let _lock = rng_buffer::lock_for_test();
+        // Two chunks side by side, with one entity about to cross from the first into the second.
+        let mut world = World::new(128, 64);
+        let mut entity = Entity::new(63, 10, &world);
+        entity.behaviour = Behaviour::Cruiser;
+        entity.facing = Direction::East;
+        world.place_entity(entity).unwrap();
+        execution::set(Execution::Sequential).unwrap();
+        set_drawing_enabled(false);
+        start(world);
+
+        step(false);
+        unsafe {
+            assert_eq!(active_chunks(), &[0, 1]);
+            assert_eq!(world_ref().populated_chunks(), [1]);
+        }
+        step(false);
+        unsafe {
+            assert_eq!(active_chunks(), &[1]);
+            let allocated = conflict_grid().as_ref().unwrap().allocated_chunks();
+            assert_eq!(allocated.iter().map(|(chunk, _)| *chunk).collect::<Vec<_>>(), [1]);
+        }
+        take_world().unwrap();
+    }
+
+    #[test]
+    fn conflict_flags_the_side_moves_come_from() {
+        let conflict = Conflict::default();
+        assert_eq!(conflict.count(), 0);
+        assert!(!conflict.is_conflicted());
+
+        // Moving south enters the cell from its north side.
+        conflict.add_from(&Direction::South);
+        assert_eq!(conflict.bits(), 0b0000_0001);
+        assert!(!conflict.is_conflicted());
+        assert!(conflict.is_first_from(&Direction::South));
+
+        // Moving northwest enters it from the southeast, and a repeated side only counts once.
+        conflict.add_from(&Direction::Northwest);
+        conflict.add_from(&Direction::Northwest);
+        assert_eq!(conflict.bits(), 0b0000_1001);
+        assert_eq!(conflict.count(), 2);
+        assert!(conflict.is_conflicted());
+        assert!(conflict.is_first_from(&Direction::South));
+        assert!(!conflict.is_first_from(&Direction::Northwest));
+
+        conflict.clear();
+        assert_eq!(conflict.count(), 0);
+    }
+
+    #[test]
+    fn first_mover_follows_compass_order_of_sides() {
+        let conflict = Conflict::default();
+        for direction in Direction::ALL.iter().rev() {
+            conflict.add_from(direction);
+        }
+        assert_eq!(conflict.count(), 8);
+        let first: Vec<_> = Direction::ALL.iter()
+            .filter(|direction| conflict.is_first_from(direction))
+            .map(Direction::index)
+            .collect();
+        assert_eq!(first, [Direction::South.index()]);
     }
 }